@@ -0,0 +1,162 @@
+use std::convert::TryFrom;
+use std::collections::HashSet;
+
+use ansi_term::Colour::Green;
+use ansi_term::Style;
+use chrono::{Datelike, Duration, Months, NaiveDate};
+
+// Visible width of a month
+pub const MONTH_WIDTH: usize = 21;
+
+const WEEKDAYS: &str = "Su Mo Tu We Th Fr Sa ";
+const MONTH_NAMES: [&str; 12] = ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+
+// Days to mark in calendars
+#[derive(Debug, Default)]
+pub struct Marks {
+    // Days with a diary page
+    pub diaries: HashSet<NaiveDate>,
+    // Days memo pages were created
+    pub memos: HashSet<NaiveDate>,
+    pub today: Option<NaiveDate>,
+}
+
+// First day of the month `offset` months after the month of the date, moved by `--next` and `--prev`
+pub fn shift_month(date: NaiveDate, offset: i64) -> Option<NaiveDate> {
+    let first = date.with_day(1)?;
+    let months = Months::new(u32::try_from(offset.unsigned_abs()).ok()?);
+    if offset >= 0 {
+        first.checked_add_months(months)
+    } else {
+        first.checked_sub_months(months)
+    }
+}
+
+// Last day of the month of the date
+pub fn last_day(date: NaiveDate) -> Option<NaiveDate> {
+    date.with_day(1)?.checked_add_months(Months::new(1))?.pred_opt()
+}
+
+// Lines of the month, each of which is `MONTH_WIDTH` characters wide when displayed
+pub fn render_month(year: i32, month: u32, marks: &Marks) -> Vec<String> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+    let last = last_day(first).unwrap();
+
+    let title = format!("{} {}", MONTH_NAMES[month as usize - 1], year);
+    let mut lines = vec![
+        format!("{:^width$}", title, width = MONTH_WIDTH),
+        WEEKDAYS.to_string(),
+    ];
+
+    let mut line = "   ".repeat(first.weekday().num_days_from_sunday() as usize);
+    let mut date = first;
+    while date <= last {
+        let mut style = Style::new();
+        if marks.diaries.contains(&date) {
+            style = Green.bold();
+        }
+        if marks.today == Some(date) {
+            style = style.reverse();
+        }
+
+        let marker = if marks.memos.contains(&date) { "*" } else { " " };
+        line.push_str(&format!("{}{}", style.paint(format!("{:>2}", date.day())), marker));
+
+        if date.weekday().num_days_from_sunday() == 6 {
+            lines.push(line);
+            line = String::new();
+        }
+        date += Duration::days(1);
+    }
+
+    if !line.is_empty() {
+        let cells = last.weekday().num_days_from_sunday() as usize + 1;
+        line.push_str(&"   ".repeat(7 - cells));
+        lines.push(line);
+    }
+
+    // Make all months the same height
+    while lines.len() < 8 {
+        lines.push(" ".repeat(MONTH_WIDTH));
+    }
+
+    lines
+}
+
+// Lines of the months of the year, `columns` months in a row
+pub fn render_year(year: i32, marks: &Marks, columns: usize) -> Vec<String> {
+    let months: Vec<Vec<String>> = (1..13).map(|month| render_month(year, month, marks)).collect();
+
+    let mut lines = Vec::new();
+    for row in months.chunks(columns) {
+        for i in 0..row[0].len() {
+            let line: Vec<&str> = row.iter().map(|month| month[i].as_str()).collect();
+            lines.push(line.join("  "));
+        }
+    }
+
+    lines
+}
+
+// Explanation of the marks
+pub fn legend() -> String {
+    format!("{} diary  dd* memo  {} today", Green.bold().paint("dd"), Style::new().reverse().paint("dd"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn render_months() {
+        let lines = render_month(2018, 5, &Marks::default());
+        assert_eq!(lines, vec![
+            "      May 2018       ",
+            "Su Mo Tu We Th Fr Sa ",
+            "       1  2  3  4  5 ",
+            " 6  7  8  9 10 11 12 ",
+            "13 14 15 16 17 18 19 ",
+            "20 21 22 23 24 25 26 ",
+            "27 28 29 30 31       ",
+            "                     ",
+        ]);
+
+        // February 2015 starts on Sunday and ends on Saturday
+        let lines = render_month(2015, 2, &Marks::default());
+        assert_eq!(lines[2], " 1  2  3  4  5  6  7 ");
+        assert_eq!(lines[5], "22 23 24 25 26 27 28 ");
+        assert_eq!(&lines[6..], &[" ".repeat(MONTH_WIDTH), " ".repeat(MONTH_WIDTH)]);
+
+        let marks = Marks {
+            diaries: vec![date(2018, 5, 2), date(2018, 5, 3)].into_iter().collect(),
+            memos: vec![date(2018, 5, 3)].into_iter().collect(),
+            today: Some(date(2018, 5, 4)),
+        };
+        let lines = render_month(2018, 5, &marks);
+        assert_eq!(lines[2], format!("       1 {} {}*{}  5 ",
+                                     Green.bold().paint(" 2"), Green.bold().paint(" 3"), Style::new().reverse().paint(" 4")));
+
+        let lines = render_year(2018, &Marks::default(), 3);
+        assert_eq!(lines.len(), 4 * 8);
+        assert_eq!(lines[0], format!("{:^21}  {:^21}  {:^21}", "January 2018", "February 2018", "March 2018"));
+        assert!(lines.iter().all(|line| line.chars().count() == MONTH_WIDTH * 3 + 4));
+        assert_eq!(render_year(2018, &Marks::default(), 4).len(), 3 * 8);
+    }
+
+    #[test]
+    fn move_months() {
+        assert_eq!(shift_month(date(2018, 5, 31), 0), Some(date(2018, 5, 1)));
+        assert_eq!(shift_month(date(2018, 5, 31), 1), Some(date(2018, 6, 1)));
+        assert_eq!(shift_month(date(2018, 5, 31), -5), Some(date(2017, 12, 1)));
+        assert_eq!(shift_month(date(2018, 12, 15), 14), Some(date(2020, 2, 1)));
+        assert_eq!(shift_month(date(2018, 5, 1), i64::MIN), None);
+        assert_eq!(shift_month(NaiveDate::MAX, 1), None);
+
+        assert_eq!(last_day(date(2020, 2, 10)), Some(date(2020, 2, 29)));
+        assert_eq!(last_day(date(2018, 12, 1)), Some(date(2018, 12, 31)));
+    }
+}
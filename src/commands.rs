@@ -1,76 +1,184 @@
+use std::collections::HashMap;
+use std::env;
 use std::fs;
-use std::process::Command;
-use std::path::Path;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
 use clap;
-use chrono::{Local};
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime};
 use ansi_term::Colour::*;
 use regex::Regex;
+use terminal_size::{terminal_size, Width};
+use toml;
+
+use diary::config::{Config, Storage};
+use diary::crypto;
+use diary::calendar::{self, Marks};
+use diary::date::{parse_date, DateRange, DIARY_ID_FORMAT};
+use diary::error::Error;
+use diary::fsck;
+use diary::git;
+use diary::html;
+use diary::import;
+use diary::index::{self, ContextLine, Field, Index, Pattern, Query};
+use diary::json;
+use diary::lock::Lock;
+use diary::markdown;
+use diary::page::Page;
+use diary::quicklog;
+use diary::revision;
+use diary::template;
+use diary::tui;
+use diary::sqlite::{SqliteStore, DATABASE_FILENAME};
+use diary::store::{self, FsStore, Store};
+use diary::utils::*;
+
+// Range of dates given by `--since`, `--until`, `--month`, `--year` and `--last`
+fn date_range(matches: &clap::ArgMatches) -> Result<DateRange, Error> {
+    let mut range = DateRange::default();
+
+    if let Some(since) = matches.value_of("since") {
+        range = range.intersect(&DateRange::new(Some(parse_date(since)?), None));
+    }
+    if let Some(until) = matches.value_of("until") {
+        range = range.intersect(&DateRange::new(None, Some(parse_date(until)?)));
+    }
+    if let Some(month) = matches.value_of("month") {
+        range = range.intersect(&DateRange::month(month)?);
+    }
+    if let Some(year) = matches.value_of("year") {
+        range = range.intersect(&DateRange::year(year)?);
+    }
+    if let Some(last) = matches.value_of("last") {
+        range = range.intersect(&DateRange::last(last, Local::now().date_naive())?);
+    }
+
+    Ok(range)
+}
 
-use config::Config;
-use utils::*;
+pub fn list(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let store = store::open(directory, config)?;
+    let range = date_range(matches)?;
 
-pub fn list(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), String> {
-    let head_id = get_head_id(directory)?;
+    // List all pages in the range unless the count is given
+    let default_count = if range.is_unbounded() && !matches.is_present("tag") { config.list_max_count as usize } else { usize::MAX };
     let page_count = match matches.value_of("n") {
-        Some(n) => n.parse::<u32>().unwrap_or(config.list_max_count),
-        None => config.list_max_count,
+        Some(n) => n.parse::<usize>().unwrap_or(default_count),
+        None => default_count,
     };
     let skip = match matches.value_of("skip") {
-        Some(skip) => skip.parse::<i32>().unwrap_or(0),
+        Some(skip) => skip.parse::<usize>().unwrap_or(0),
         None => 0,
     };
 
-    let mut prev_id = head_id;
-    let mut i = 0;
-    loop {
-        if i >= page_count as i32 + skip {
-            break;
-        }
+    let tags: Vec<&str> = matches.values_of("tag").map(|tags| tags.collect()).unwrap_or_default();
 
-        if prev_id == "NULL" {
-            break;
+    for page in store.find(&range, &tags)?.into_iter().skip(skip).take(page_count) {
+        if matches.is_present("no-color") {
+            println!("{} ({})", page.header.title, page.id);
+        } else {
+            println!("{} ({})", page.header.title, Yellow.paint(page.id));
         }
+    }
 
-        let page = get_page_by_id(directory, &prev_id)?;
+    Ok(())
+}
 
-        if i >= skip {
-            println!("{} ({})", page.header.title, Yellow.paint(page.id));
-        }
+// Text given by `-m` or read from stdin by `--stdin`
+fn text_from_args(matches: &clap::ArgMatches) -> Result<Option<String>, Error> {
+    if let Some(message) = matches.value_of("message") {
+        return Ok(Some(message.to_string()));
+    }
 
-        prev_id = page.header.prev;
-        i += 1;
+    if matches.is_present("stdin") {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)
+            .map_err(|err| Error::io(String::from("Unable to read stdin"), err))?;
+        return Ok(Some(text));
     }
 
-    Ok(())
+    Ok(None)
 }
 
-pub fn create_new(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), String> {
+pub fn create_new(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
     let (id, memo) = match matches.value_of("id") {
         Some(id) => (id.to_string(), true),
         None => {
             // Return current date
             let now = Local::now();
-            (now.format("%Y-%m-%d").to_string(), false)
+            (now.format("%Y-%m-%d").to_string(), matches.is_present("memo"))
         },
     };
 
-    let page = TemporaryPage {
-        header: TemporaryPageHeader {
-            title: id.clone(),
-            insert_title: true,
-            memo,
+    let mut store = store::open(directory, config)?;
+    let mut page = template::initial_page(directory, config, store.as_ref(), &id, memo, matches.value_of("template"))?;
+    if let Some(title) = matches.value_of("title") {
+        page.header.title = title.to_string();
+    }
+
+    match text_from_args(matches)? {
+        // The text follows the template
+        Some(text) => {
+            page.text = append_text(&page.text, &text);
+            create_page(store.as_mut(), directory, &id, &page)?;
         },
-        text: String::new(),
-    };
+        None => create_new_page(store.as_mut(), directory, &id, &config.editor, &page)?,
+    }
+
+    Ok(())
+}
 
-    create_new_page(directory, &id, &config.editor, &page)?;
+pub fn append(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let text = text_from_args(matches)?.unwrap();
+    let mut store = store::open(directory, config)?;
+
+    match matches.value_of("id") {
+        Some(id) => append_to_page(store.as_mut(), directory, id, &text, &config.revisions)?,
+        None => append_to_today(store.as_mut(), directory, config, &text)?,
+    }
 
     Ok(())
 }
 
+// Append to today's page, which is created like `diary` does if it does not exist yet
+fn append_to_today(store: &mut dyn Store, directory: &str, config: &Config, text: &str) -> Result<(), Error> {
+    let id = Local::now().format("%Y-%m-%d").to_string();
+    if store.exists(&id)? {
+        append_to_page(store, directory, &id, text, &config.revisions)
+    } else if text.trim().is_empty() {
+        Ok(())
+    } else {
+        let mut page = template::initial_page(directory, config, store, &id, false, None)?;
+        page.text = append_text(&page.text, text);
+        create_page(store, directory, &id, &page)
+    }
+}
 
-pub fn edit(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), String> {
+// Append a line with the local time to today's page, or print the lines with `--show`.
+// `diary log <id>` of an existing page shows commits changing it if the history is enabled.
+pub fn log(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let mut store = store::open(directory, config)?;
+
+    if matches.is_present("show") {
+        for line in quicklog::lines(store.as_ref(), &Local::now().date_naive())? {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    let message: Vec<&str> = matches.values_of("message").unwrap().collect();
+    if message.len() == 1 && git::is_repository(directory) && store.exists(message[0])? {
+        return git::log(directory, message[0]);
+    }
+
+    let line = quicklog::entry(&Local::now().time(), &message.join(" "))
+        .ok_or_else(|| Error::Parse(String::from("The log message is empty")))?;
+    append_to_today(store.as_mut(), directory, config, &line)?;
+
+    Ok(())
+}
+
+pub fn edit(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
     let id = match matches.value_of("id") {
         Some(id) => id.to_string(),
         None => {
@@ -80,64 +188,75 @@ pub fn edit(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Res
         },
     };
 
-    edit_page_by_id(directory, &id, &config.editor)?;
+    let mut store = store::open(directory, config)?;
+    edit_page_by_id(store.as_mut(), directory, &id, &config.editor, &config.revisions)?;
 
     Ok(())
 }
 
-pub fn config(directory: &str, config: &Config, _matches: &clap::ArgMatches) -> Result<(), String> {
+pub fn config(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
     let config_path = Path::new(directory).join("config.toml");
-
-    // Execute editor
-    let mut command =
-        if cfg!(target_os = "windows") {
-            Command::new("cmd")
-                .args(&["/c", &format!("{} {}", &config.editor, config_path.to_string_lossy())])
-                .spawn()
-                .map_err(|err| format!("Unable to execute editor `cmd /c {}`: {}", &config.editor, err))?
-        } else {
-            Command::new("sh")
-                .args(&["-c", &format!("{} {}", &config.editor, config_path.to_string_lossy())])
-                .spawn()
-                .map_err(|err| format!("Unable to execute editor `sh -c {}`: {}", &config.editor, err))?
-        };
-
-    let status = command.wait()
-        .map_err(|err| format!("Unable to wait editor `{}`: {}", &config.editor, err))?;
-
-    // Error if exit code is not 0
-    if !status.success() {
-        return Err(format!("Failed editor `{}`", config.editor));
+    let values = || config.values().map_err(|err| Error::Parse(format!("Unable to list settings: {}", err)));
+
+    match matches.subcommand() {
+        ("get", Some(matches)) => {
+            let key = matches.value_of("key").unwrap();
+            match values()?.into_iter().find(|(name, _)| name == key) {
+                // Strings are printed without quotes
+                Some((_, toml::Value::String(value))) => println!("{}", value),
+                Some((_, value)) => println!("{}", value),
+                None => return Err(Error::NotFound(format!("`{}` is not set. see `diary config list`", key))),
+            }
+        },
+        ("set", Some(matches)) => {
+            let key = matches.value_of("key").unwrap();
+            let contents = if config_path.exists() {
+                read_file(&config_path)
+                    .map_err(|err| Error::io(format!("Unable to read `{}`", config_path.to_string_lossy()), err))?
+            } else {
+                String::new()
+            };
+
+            let contents = Config::set_value(&contents, key, matches.value_of("value").unwrap())
+                .map_err(|err| Error::Parse(format!("Unable to set `{}`: {}", key, err)))?;
+
+            let (new_config, _) = Config::parse(&contents)
+                .map_err(|err| Error::Parse(format!("Unable to set `{}`: {}", key, err)))?;
+            if new_config.git {
+                git::ensure_supported(directory, &new_config)?;
+            }
+            write_file(&config_path, &contents)
+                .map_err(|err| Error::io(format!("Unable to write `{}`", config_path.to_string_lossy()), err))?;
+        },
+        ("list", _) => {
+            for (key, value) in values()? {
+                println!("{} = {}", key, value);
+            }
+        },
+        _ => run_editor(&config.editor, &config_path)?,
     }
 
     Ok(())
 }
 
-pub fn diary(directory: &str, config: &Config, _matches: &clap::ArgMatches) -> Result<(), String> {
+pub fn diary(directory: &str, config: &Config, _matches: &clap::ArgMatches) -> Result<(), Error> {
     let now = Local::now();
     let id = now.format("%Y-%m-%d").to_string();
 
-    let today_page_path = Path::new(directory).join(PAGES_DIR).join(format!("{}.{}", id, PAGE_EXTENSION));
-    if today_page_path.exists() {
-        // Edit if today page file exists
-        edit_page_by_id(directory, &id, &config.editor)?;
+    let mut store = store::open(directory, config)?;
+    if store.exists(&id)? {
+        // Edit if today page exists
+        edit_page_by_id(store.as_mut(), directory, &id, &config.editor, &config.revisions)?;
     } else {
-        let page = TemporaryPage {
-            header: TemporaryPageHeader {
-                title: id.clone(),
-                insert_title: true,
-                memo: false,
-            },
-            text: String::new(),
-        };
-        // Create new if today page file does not exists
-        create_new_page(directory, &id, &config.editor, &page)?;
+        let page = template::initial_page(directory, config, store.as_ref(), &id, false, None)?;
+        // Create new if today page does not exists
+        create_new_page(store.as_mut(), directory, &id, &config.editor, &page)?;
     }
 
     Ok(())
 }
 
-pub fn show(directory: &str, _config: &Config, matches: &clap::ArgMatches) -> Result<(), String> {
+pub fn show(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
     let mut id = match matches.value_of("id") {
         Some(id) => id.to_string(),
         None => {
@@ -147,75 +266,505 @@ pub fn show(directory: &str, _config: &Config, matches: &clap::ArgMatches) -> Re
         },
     };
 
-    let path = Path::new(directory).join(PAGES_DIR).join(format!("{}.{}", id, PAGE_EXTENSION));
-    if !path.exists() {
+    let store = store::open(directory, config)?;
+    if !store.exists(&id)? {
         // search by regex
-        let re = Regex::new(&id).map_err(|err| format!("Invalid regex: {}", err))?;
-        let pages_dir = Path::new(directory).join(PAGES_DIR);
-        let path_list = fs::read_dir(&pages_dir)
-            .map_err(|err| format!("Unable to list files in directory `{}`: {}", pages_dir.to_string_lossy(), err))?;
+        let re = Regex::new(&id).map_err(|err| Error::Parse(format!("Invalid regex: {}", err)))?;
 
         let mut matched = false;
-        for path in path_list {
-            let path = path.unwrap().path();
-            let name = path.file_stem().unwrap().to_str().unwrap();
-            if re.is_match(name) {
-                id = name.to_string();
+        for name in store.ids()? {
+            if re.is_match(&name) {
+                id = name;
                 matched = true;
             }
         }
 
         if !matched {
-            return Err(String::from("Not found"));
+            return Err(Error::NotFound(String::from("Not found")));
         }
     }
 
-    let page = get_page_by_id(directory, &id)?;
+    let page = match matches.value_of("rev") {
+        Some(rev) => store.get_revision(&id, parse_revision(rev)?)?,
+        None => store.get(&id)?,
+    };
 
+    let mut contents = String::new();
     if page.header.insert_title {
-        println!("# {}\n", page.header.title);
+        contents.push_str(&format!("# {}\n\n", page.header.title));
     }
+    contents.push_str(&page.text);
 
-    println!("{}", page.text);
+    if matches.is_present("markdown") {
+        let width = terminal_size().map(|(Width(width), _)| width as usize)
+            .or_else(|| env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()))
+            .unwrap_or(markdown::DEFAULT_WIDTH);
+        contents = markdown::render(&contents, width, !matches.is_present("no-color"));
+    }
+
+    if matches.is_present("pager") {
+        let pager = env::var("PAGER").unwrap_or_else(|_| String::from(if cfg!(target_os = "windows") { "more" } else { "less -R" }));
+        run_pager(&pager, &format!("{}\n", contents))?;
+    } else {
+        println!("{}", contents);
+    }
 
     Ok(())
 }
 
-pub fn search(directory: &str, _config: &Config, matches: &clap::ArgMatches) -> Result<(), String> {
+// Print lines of the text containing the ranges with `context` lines around them, like grep
+fn print_context(text: &str, ranges: &[(usize, usize)], context: usize) {
+    for line in index::context_lines(text, ranges, context) {
+        match line {
+            ContextLine::Line { number, start, end, matched: true } => println!("{}:{}", Green.paint(number.to_string()), index::paint_ranges(text, start, end, ranges)),
+            ContextLine::Line { number, start, end, matched: false } => println!("{}-{}", Green.paint(number.to_string()), &text[start..end]),
+            ContextLine::Separator => println!("--"),
+        }
+    }
+}
+
+pub fn search(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
     let query = matches.value_of("query").unwrap();
-    let head_id = get_head_id(directory)?;
+    let context = match matches.value_of("context") {
+        Some(context) => Some(context.parse::<usize>().map_err(|err| Error::Parse(format!("Invalid context `{}`: {}", context, err)))?),
+        None => None,
+    };
 
-    let is_title = matches.is_present("title");
+    let tags: Vec<&str> = matches.values_of("tag").map(|tags| tags.collect()).unwrap_or_default();
+    let store = store::open(directory, config)?;
 
-    let mut prev_id = head_id;
-    loop {
-        if prev_id == "NULL" {
-            break;
-        }
+    // Matched pages and ranges of matches in their text
+    let mut results = Vec::new();
 
-        let page = get_page_by_id(directory, &prev_id)?;
+    if matches.is_present("regex") || matches.is_present("ignore-case") || matches.is_present("word") {
+        // Match the pattern against each page
+        let mut pattern = Pattern::parse(query, matches.is_present("regex"), matches.is_present("ignore-case"), matches.is_present("word"))?;
+        if matches.is_present("title") {
+            pattern.field = Some(Field::Title);
+        }
 
-        if is_title {
-            if page.header.title.contains(query) {
-                println!("{} ({})", page.header.title, Yellow.paint(page.id));
-            }
-        } else {
-            if page.text.contains(query) || page.header.title.contains(query) {
-                println!("{} ({})", page.header.title, Yellow.paint(page.id));
+        for page in store.find(&DateRange::default(), &tags)? {
+            if let Some(ranges) = pattern.matches(&page) {
+                results.push((page, ranges));
             }
         }
+    } else {
+        let query = Query::parse(query)?;
+        let field = if matches.is_present("title") { Field::Title } else { Field::All };
+        // The rebuilt index is saved only while holding the lock
+        let index = match Lock::acquire(directory) {
+            Ok(_lock) => Index::open(directory, store.as_ref())?,
+            Err(_) => Index::load(directory, store.as_ref())?,
+        };
+
+        for (id, _) in index.search(&query, field) {
+            // Skip pages changed without updating the index
+            let page = match store.get(&id) {
+                Ok(page) => page,
+                Err(Error::NotFound(_)) => continue,
+                Err(err) => return Err(err),
+            };
+
+            let ranges = query.highlights(&page.text);
+            results.push((page, ranges));
+        }
+    }
 
-        prev_id = page.header.prev;
+    for (page, ranges) in results.into_iter().filter(|(page, _)| page.has_tags(&tags)) {
+        println!("{} ({})", page.header.title, Yellow.paint(page.id));
+        match context {
+            Some(context) => print_context(&page.text, &ranges, context),
+            None => {
+                if let Some(snippet) = index::snippet(&page.text, &ranges) {
+                    println!("    {}", snippet);
+                }
+            },
+        }
     }
 
     Ok(())
 }
 
-pub fn editid(directory: &str, _config: &Config, matches: &clap::ArgMatches) -> Result<(), String> {
+pub fn editid(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
     let prev_id = matches.value_of("prev_id").unwrap();
     let next_id = matches.value_of("next_id").unwrap();
 
-    edit_id(directory, prev_id, next_id)?;
+    let mut store = store::open(directory, config)?;
+    store.rename(prev_id, next_id)?;
+    index::rename_page(directory, store.as_ref(), prev_id, next_id)?;
+
+    Ok(())
+}
+
+pub fn remove(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let id = matches.value_of("id").unwrap();
+    let trash = matches.is_present("trash");
+
+    let mut store = store::open(directory, config)?;
+    store.delete(id, trash)?;
+    index::remove_page(directory, id)?;
+
+    Ok(())
+}
+
+pub fn fsck(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let mut store = store::open(directory, config)?;
+    let problems = fsck::check(store.as_ref())?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+
+    if matches.is_present("repair") {
+        let count = fsck::repair(store.as_mut())?;
+        println!("Rebuilt the chain of {} pages", count);
+    } else if !problems.is_empty() {
+        return Err(Error::Corrupted(format!("{} problems found. use `diary fsck --repair`", problems.len())));
+    }
+
+    Ok(())
+}
+
+pub fn recover(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let recoveries = get_recoveries(directory)?;
+
+    let name = match matches.value_of("name") {
+        Some(name) => name,
+        None => {
+            // List recoveries
+            for (path, time, id) in recoveries {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                let time = NaiveDateTime::parse_from_str(&time, RECOVERY_TIME_FORMAT)
+                    .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or(time);
+                println!("{} {} ({})", time, id, Yellow.paint(name));
+            }
+            return Ok(());
+        },
+    };
+
+    // Find by the file name, or the latest recovery of the id
+    let recovery = recoveries.iter()
+        .find(|(path, _, _)| path.file_name().map(|file_name| file_name == name).unwrap_or(false))
+        .or_else(|| recoveries.iter().rev().find(|(_, _, id)| id == name));
+    let (path, _, id) = match recovery {
+        Some(recovery) => recovery,
+        None => return Err(Error::NotFound(String::from("Not found"))),
+    };
+
+    if matches.is_present("discard") {
+        fs::remove_file(path)
+            .map_err(|err| Error::io(format!("Unable to remove recovery file `{}`", path.to_string_lossy()), err))?;
+    } else {
+        let mut store = store::open(directory, config)?;
+        recover_edit_file(store.as_mut(), directory, path, id, &config.editor, &config.revisions)?;
+    }
+
+    Ok(())
+}
+
+pub fn migrate(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let from = FsStore::new(directory);
+    let mut to = SqliteStore::open(directory)?;
+
+    if from.ids()?.is_empty() {
+        return Err(Error::NotFound(String::from("There are no pages to migrate in `pages/`")));
+    }
+    if !to.ids()?.is_empty() && !matches.is_present("force") {
+        return Err(Error::AlreadyExists(String::from("The database already has pages. use `diary migrate --force` to overwrite them")));
+    }
+
+    let count = store::copy(&from, &mut to)?;
+    println!("Migrated {} pages to `{}`", count, Path::new(directory).join(DATABASE_FILENAME).to_string_lossy());
+
+    if config.storage != Storage::Sqlite {
+        println!("Set `storage = \"sqlite\"` in config.toml to use it");
+    }
 
     Ok(())
 }
+
+pub fn export(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("pages", Some(matches)) => export_pages(directory, config, matches),
+        ("html", Some(matches)) => export_html(directory, config, matches),
+        _ => export_json(directory, config, matches),
+    }
+}
+
+// Write all pages in JSON or JSON Lines to the file or stdout
+fn export_json(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let format = match matches.value_of("format") {
+        Some(format) => json::Format::from_name(format)?,
+        None => return Err(Error::Parse(String::from("use `diary export pages|html <outdir>` or `diary export --format json|jsonl`"))),
+    };
+
+    let store = store::open(directory, config)?;
+    let contents = json::to_string(&json::load_all(store.as_ref())?, format);
+
+    match matches.value_of("output") {
+        Some(output) => {
+            let path = PathBuf::from(output);
+            write_file(&path, &contents)
+                .map_err(|err| Error::io(format!("Unable to write `{}`", output), err))?;
+        },
+        None => print!("{}", contents),
+    }
+
+    Ok(())
+}
+
+// Write pages in the `.page` format to `<outdir>/pages` and the head id to `<outdir>/HEAD`
+fn export_pages(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let outdir = matches.value_of("outdir").unwrap();
+    let pages_dir = Path::new(outdir).join(PAGES_DIR);
+    fs::create_dir_all(&pages_dir)
+        .map_err(|err| Error::io(format!("Unable to create directory `{}`", pages_dir.to_string_lossy()), err))?;
+
+    let from = store::open(directory, config)?;
+    let mut to = FsStore::new(outdir);
+    let count = store::copy(from.as_ref(), &mut to)?;
+    println!("Exported {} pages to `{}`", count, outdir);
+
+    Ok(())
+}
+
+// Render pages into a static HTML site in `<outdir>`
+fn export_html(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let outdir = matches.value_of("outdir").unwrap();
+
+    let store = store::open(directory, config)?;
+    let count = html::export(store.as_ref(), Path::new(outdir))?;
+    println!("Exported {} pages to `{}`", count, Path::new(outdir).join("index.html").to_string_lossy());
+
+    Ok(())
+}
+
+pub fn cal(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let today = Local::now().date_naive();
+
+    // Range of dates to render, and the first day of the month unless rendering the year
+    let (range, month) = if let Some(year) = matches.value_of("year") {
+        (DateRange::year(year)?, None)
+    } else {
+        let month = match matches.value_of("month") {
+            Some(month) => DateRange::month(month)?.since.unwrap(),
+            None => today.with_day(1).unwrap(),
+        };
+
+        // Move by `--prev` and `--next`
+        let offset = matches.occurrences_of("next") as i64 - matches.occurrences_of("prev") as i64;
+        let month = calendar::shift_month(month, offset)
+            .ok_or_else(|| Error::Parse(String::from("The month is out of range")))?;
+
+        let last = calendar::last_day(month);
+        (DateRange::new(Some(month), last), Some(month))
+    };
+
+    let store = store::open(directory, config)?;
+    let mut marks = Marks { today: Some(today), ..Marks::default() };
+    for page in store.find(&range, &[])? {
+        if let Ok(date) = NaiveDate::parse_from_str(&page.id, DIARY_ID_FORMAT) {
+            marks.diaries.insert(date);
+        }
+        if page.header.memo {
+            marks.memos.insert(page.header.created.with_timezone(&Local).date_naive());
+        }
+    }
+
+    let lines = match month {
+        Some(month) => calendar::render_month(month.year(), month.month(), &marks),
+        None => calendar::render_year(range.since.unwrap().year(), &marks, 3),
+    };
+
+    for line in lines {
+        println!("{}", line.trim_end());
+    }
+    println!("{}", calendar::legend());
+
+    Ok(())
+}
+
+pub fn tags(directory: &str, config: &Config, _matches: &clap::ArgMatches) -> Result<(), Error> {
+    let store = store::open(directory, config)?;
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for page in store.find(&DateRange::default(), &[])? {
+        for tag in page.tags() {
+            *counts.entry(tag).or_insert(0) += 1;
+        }
+    }
+
+    // Most used first
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    for (tag, count) in counts {
+        println!("{} ({})", tag, Yellow.paint(count.to_string()));
+    }
+
+    Ok(())
+}
+
+pub fn import(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let file = matches.value_of("file").unwrap();
+    let mut store = store::open(directory, config)?;
+
+    let pages = match matches.value_of("format").and_then(import::importer) {
+        // Entries of another journaling tool
+        Some(importer) => {
+            let entries = importer.parse(Path::new(file))?;
+            import::to_pages(store.as_ref(), entries)?
+        },
+        None => {
+            let format = match matches.value_of("format") {
+                Some(format) => Some(json::Format::from_name(format)?),
+                None => None,
+            };
+
+            let contents = if file == "-" {
+                let mut contents = String::new();
+                io::stdin().read_to_string(&mut contents)
+                    .map_err(|err| Error::io(String::from("Unable to read stdin"), err))?;
+                contents
+            } else {
+                read_file(&PathBuf::from(file))
+                    .map_err(|err| Error::io(format!("Unable to read `{}`", file), err))?
+            };
+            json::from_str(&contents, format)?
+        },
+    };
+
+    let count = import::import(store.as_mut(), pages.clone())?;
+
+    let mut index = Index::open(directory, store.as_ref())?;
+    for page in &pages {
+        index.add(page);
+    }
+    index.save(directory)?;
+
+    println!("Imported {} pages", count);
+
+    Ok(())
+}
+
+pub fn encrypt(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    if config.storage != Storage::Files {
+        return Err(Error::Encryption(String::from("Encryption is supported only with `storage = \"files\"`")));
+    }
+    if config.git {
+        return Err(Error::Encryption(String::from("Encryption is not supported with the git history. set `git = false` in config.toml")));
+    }
+
+    // Previous versions of pages in the history are not encrypted
+    let purge = git::is_repository(directory);
+    if purge && !matches.is_present("purge-history") {
+        return Err(Error::Encryption(format!("The git history in `{}` keeps all pages in plain text. use `--purge-history` to remove it", Path::new(directory).join(".git").to_string_lossy())));
+    }
+
+    let count = crypto::encrypt(directory)?;
+    println!("Encrypted {} pages. The passphrase cannot be recovered if it is forgotten", count);
+
+    if purge {
+        git::purge(directory)?;
+        println!("Removed the git history. Copies pushed to remotes are not removed");
+    }
+
+    Ok(())
+}
+
+pub fn decrypt(directory: &str, _config: &Config, _matches: &clap::ArgMatches) -> Result<(), Error> {
+    let count = crypto::decrypt(directory)?;
+    println!("Decrypted {} pages", count);
+
+    Ok(())
+}
+
+fn parse_revision(s: &str) -> Result<u32, Error> {
+    s.parse().map_err(|_| Error::Parse(format!("Invalid revision `{}`. use the number in `diary history`", s)))
+}
+
+// Show changes between revisions, or changes in the git history if the revision is not a number
+pub fn diff(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let id = matches.value_of("id").unwrap();
+    let rev = matches.value_of("rev");
+
+    let store = store::open(directory, config)?;
+    let numbers = store.revisions(id)?;
+    let (from, to) = match revision::diff_source(id, rev, &numbers, git::is_repository(directory))? {
+        revision::DiffSource::Revisions(from, to) => (from, to),
+        revision::DiffSource::Git(rev) => return git::diff(directory, id, rev),
+    };
+
+    let old = revision::contents(&store.get_revision(id, from)?)?;
+    let (new, new_name) = match to {
+        Some(to) => (revision::contents(&store.get_revision(id, to)?)?, format!("{}@{}", id, to)),
+        None => (revision::contents(&store.get(id)?)?, format!("{}@current", id)),
+    };
+
+    let diff = revision::unified_diff(&old, &new, &format!("{}@{}", id, from), &new_name, !matches.is_present("no-color"));
+    if !diff.is_empty() {
+        println!("{}", diff.trim_end());
+    }
+
+    Ok(())
+}
+
+// List revisions of the page, newest first
+pub fn history(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let id = matches.value_of("id").unwrap();
+
+    let store = store::open(directory, config)?;
+    let page = store.get(id)?;
+    let time = |page: &Page| revision::saved_time(page).with_timezone(&Local).format("%Y-%m-%d %H:%M");
+
+    println!("{} {}  {}", Yellow.paint("current"), time(&page), page.header.title);
+    for number in store.revisions(id)?.into_iter().rev() {
+        let revision = store.get_revision(id, number)?;
+        println!("{} {}  {}", Yellow.paint(format!("{:>7}", number)), time(&revision), revision.header.title);
+    }
+
+    Ok(())
+}
+
+pub fn revert(directory: &str, config: &Config, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let id = matches.value_of("id").unwrap();
+    let number = parse_revision(matches.value_of("rev").unwrap())?;
+
+    let mut store = store::open(directory, config)?;
+    let page = store.get(id)?;
+    let restored = revision::restore(&page, &store.get_revision(id, number)?);
+    if revision::contents(&restored)? == revision::contents(&page)? {
+        println!("`{}` is already the same as revision {}", id, number);
+        return Ok(());
+    }
+
+    let changes = revision::save_changes(store.as_ref(), &page, &restored, &config.revisions)?;
+    store.apply(changes)?;
+    index::update_page(directory, &restored)?;
+
+    println!("Reverted `{}` to revision {}", id, number);
+
+    Ok(())
+}
+
+pub fn sync(directory: &str, config: &Config, _matches: &clap::ArgMatches) -> Result<(), Error> {
+    let remote = match config.git_remote {
+        Some(ref remote) => remote,
+        None => return Err(Error::NotFound(String::from("No remote to sync with. set `git_remote` in config.toml"))),
+    };
+
+    if git::sync(directory, remote)? {
+        // Rebuild the index for pulled pages
+        let store = store::open(directory, config)?;
+        Index::build(store.as_ref())?.save(directory)?;
+        println!("Pulled and pushed changes");
+    } else {
+        println!("Pushed changes");
+    }
+
+    Ok(())
+}
+
+pub fn tui(directory: &str, config: &Config, _matches: &clap::ArgMatches) -> Result<(), Error> {
+    tui::run(directory, config)
+}
@@ -1,25 +1,225 @@
+use std::env;
 use std::path::Path;
 use std::io::Read;
 
 use std::fs;
 use toml;
+use toml_edit::{self, DocumentMut};
+use serde_ignored;
 use failure;
 
-#[derive(Debug, Deserialize)]
+// Where pages are stored
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    // `pages/<id>.page` files
+    #[default]
+    Files,
+    // SQLite database `diary.db`
+    Sqlite,
+}
+
+// How long previous versions of pages are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    // Revisions kept for each page. 0 disables revisions.
+    pub max_count: u32,
+    // Revisions saved more than this many days ago are removed
+    pub max_days: Option<u32>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            max_count: 50,
+            max_days: None,
+        }
+    }
+}
+
+// Names of templates used by default, `templates/<name>.md`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Templates {
+    pub diary: String,
+    pub memo: String,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Templates {
+            diary: String::from("diary"),
+            memo: String::from("memo"),
+        }
+    }
+}
+
+// Every field is optional. Missing fields take the default values.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    // `$VISUAL`, `$EDITOR` or `vi` by default
     pub editor: String,
     pub list_max_count: u32,
+    pub storage: Storage,
+    // Commit changes to the git repository in the app directory
+    pub git: bool,
+    // Repository to pull from and push to by `diary sync`
+    pub git_remote: Option<String>,
+    // `[revisions]` table
+    pub revisions: Retention,
+    // `[templates]` table
+    pub templates: Templates,
+}
+
+// The first editor set in the environment
+fn default_editor() -> String {
+    ["VISUAL", "EDITOR"].iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| String::from("vi"))
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            editor: default_editor(),
+            list_max_count: 7,
+            storage: Storage::default(),
+            git: false,
+            git_remote: None,
+            revisions: Retention::default(),
+            templates: Templates::default(),
+        }
+    }
+}
+
+// Add `key = value` pairs of the value to `values`, joining keys of nested tables with `.`
+fn flatten(prefix: &str, value: &toml::Value, values: &mut Vec<(String, toml::Value)>) {
+    match *value {
+        toml::Value::Table(ref table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&key, value, values);
+            }
+        },
+        _ => values.push((prefix.to_string(), value.clone())),
+    }
+}
+
+// Set the item of the dotted key, creating tables on the way
+fn set_item(document: &mut DocumentMut, key: &str, value: toml_edit::Value) -> Result<(), failure::Error> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap();
+
+    let mut table = document.as_table_mut();
+    for part in parts {
+        table = table.entry(part).or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .ok_or_else(|| format_err!("`{}` is not a table", part))?;
+    }
+    table[last] = toml_edit::value(value);
+
+    Ok(())
 }
 
 impl Config {
-    // Load TOML file
-    pub fn load_from_file(filepath: &Path) -> Result<Config, failure::Error> {
+    // Load TOML file. A missing file is the same as an empty one.
+    // Returns the config and the keys which are not used, like `revisions.max_cuont`.
+    pub fn load_from_file(filepath: &Path) -> Result<(Config, Vec<String>), failure::Error> {
+        if !filepath.exists() {
+            return Ok((Config::default(), Vec::new()));
+        }
+
         let mut file = fs::File::open(filepath)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
 
-        let config: Config = toml::from_str(&contents)?;
+        Config::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<(Config, Vec<String>), failure::Error> {
+        let mut unknown_keys = Vec::new();
+        let mut deserializer = toml::Deserializer::new(contents);
+        let config = serde_ignored::deserialize(&mut deserializer, |path| unknown_keys.push(path.to_string()))?;
+        deserializer.end()?;
+
+        Ok((config, unknown_keys))
+    }
+
+    // Values of all settings including defaults with dotted keys like `revisions.max_count`.
+    // Unset optional settings are not included.
+    pub fn values(&self) -> Result<Vec<(String, toml::Value)>, failure::Error> {
+        let mut values = Vec::new();
+        flatten("", &toml::Value::try_from(self)?, &mut values);
+        Ok(values)
+    }
+
+    // Contents of the config file with the setting changed, keeping comments and formatting.
+    // The value is written as a string if it is not a valid TOML value of the setting.
+    pub fn set_value(contents: &str, key: &str, value: &str) -> Result<String, failure::Error> {
+        let mut candidates = Vec::new();
+        if let Ok(value) = value.parse::<toml_edit::Value>() {
+            candidates.push(value);
+        }
+        candidates.push(toml_edit::Value::from(value));
+
+        // Comments at the top of the file, like the initial one, stay above new settings
+        let header_len: usize = contents.split_inclusive('\n')
+            .take_while(|line| line.trim().is_empty() || line.trim_start().starts_with('#'))
+            .map(|line| line.len())
+            .sum();
+        let (header, body) = contents.split_at(header_len);
+        let header = if header.is_empty() || header.ends_with('\n') { header.to_string() } else { format!("{}\n", header) };
+
+        let mut last_error = None;
+        for candidate in candidates {
+            let mut document: DocumentMut = body.parse()?;
+            set_item(&mut document, key, candidate)?;
+
+            let contents = format!("{}{}", header, document);
+            match Config::parse(&contents) {
+                Ok((_, ref unknown_keys)) if unknown_keys.iter().any(|unknown| unknown == key) => {
+                    return Err(format_err!("Unknown key `{}`", key));
+                },
+                Ok(_) => return Ok(contents),
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        Err(last_error.unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_and_unknown_keys() {
+        let (config, unknown_keys) = Config::parse("editor = 'nano'\nlist_max_conut = 3\n[revisions]\nmax_days = 30\ncolor = true\n").unwrap();
+        assert_eq!(config.editor, "nano");
+        assert_eq!(config.list_max_count, 7);
+        assert_eq!(config.revisions.max_count, 50);
+        assert_eq!(config.revisions.max_days, Some(30));
+        assert_eq!(unknown_keys, vec!["list_max_conut", "revisions.color"]);
+    }
+
+    #[test]
+    fn set_values() {
+        let contents = "# diary\n\neditor = 'nano'\n";
+        let contents = Config::set_value(contents, "list_max_count", "10").unwrap();
+        let contents = Config::set_value(&contents, "revisions.max_days", "30").unwrap();
+        let contents = Config::set_value(&contents, "git_remote", "git@example.com:diary.git").unwrap();
+        assert!(contents.starts_with("# diary\n\neditor = 'nano'\n"));
+
+        let (config, _) = Config::parse(&contents).unwrap();
+        assert_eq!(config.list_max_count, 10);
+        assert_eq!(config.revisions.max_days, Some(30));
+        assert_eq!(config.git_remote.as_deref(), Some("git@example.com:diary.git"));
 
-        Ok(config)
+        assert!(Config::set_value(&contents, "list_max_count", "many").is_err());
+        assert!(Config::set_value(&contents, "colour", "true").is_err());
     }
 }
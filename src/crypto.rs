@@ -0,0 +1,414 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rpassword;
+use toml;
+
+use error::Error;
+use index::{INDEX_FILENAME, INDEX_LOG_FILENAME};
+use journal::Journal;
+use store::{FsStore, Store};
+use utils::{edit_dir, read_file, PAGES_DIR, PAGE_EXTENSION, RECOVERY_DIR, REVISIONS_DIR, TEMPORARY_FILE_TO_EDIT, TRASH_DIR};
+
+// File saving the salt and the key derivation parameters. Its existence enables encryption.
+pub const ENCRYPTION_FILENAME: &str = "ENCRYPTION";
+// Environment variable to pass the passphrase without prompting
+pub const PASSPHRASE_ENV: &str = "DIARY_PASSPHRASE";
+
+// Prefix of encrypted file contents
+const MAGIC: &str = "diary-encrypted:2:";
+// Prefix of contents encrypted by older versions without binding them to their paths
+const LEGACY_MAGIC: &str = "diary-encrypted:1:";
+// Version of the key file. Diaries of version 1 may contain files of `LEGACY_MAGIC`.
+const KEY_FILE_VERSION: u32 = 2;
+// Plain text encrypted into the key file to check the passphrase
+const CHECK_TEXT: &str = "diary";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+
+// Passphrase entered in this process, to prompt only once
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyFile {
+    version: u32,
+    salt: String,
+    // Argon2id parameters
+    memory_cost: u32,
+    time_cost: u32,
+    parallelism: u32,
+    // `CHECK_TEXT` encrypted with the key
+    check: String,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+// Authenticated encryption of file contents with a key derived from the passphrase.
+// Contents are bound to the path of the file relative to the app directory, so that a revision or
+// a page in the trash cannot be moved into `pages/` in place of the page.
+pub struct Cipher {
+    cipher: XChaCha20Poly1305,
+    // Accept contents which are not bound to their paths
+    legacy: bool,
+}
+
+// Path relative to the app directory with `/` separators on every platform
+fn associated_data(file: &Path) -> String {
+    file.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+impl Cipher {
+    fn derive(passphrase: &str, key_file: &KeyFile) -> Result<Cipher, Error> {
+        let salt = from_hex(&key_file.salt)
+            .ok_or_else(|| Error::Corrupted(format!("Invalid salt in `{}`", ENCRYPTION_FILENAME)))?;
+        let params = Params::new(key_file.memory_cost, key_file.time_cost, key_file.parallelism, Some(32))
+            .map_err(|err| Error::Corrupted(format!("Invalid key parameters in `{}`: {}", ENCRYPTION_FILENAME, err)))?;
+
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|err| Error::Encryption(format!("Unable to derive the key: {}", err)))?;
+
+        Ok(Cipher {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(&key)),
+            legacy: key_file.version < KEY_FILE_VERSION,
+        })
+    }
+
+    // Encrypt contents of the file at `file` relative to the app directory
+    pub fn encrypt(&self, plaintext: &str, file: &Path) -> Result<String, Error> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = associated_data(file);
+        let ciphertext = self.cipher.encrypt(&nonce, Payload { msg: plaintext.as_bytes(), aad: aad.as_bytes() })
+            .map_err(|_| Error::Encryption("Unable to encrypt".to_string()))?;
+
+        Ok(format!("{}{}{}\n", MAGIC, to_hex(&nonce), to_hex(&ciphertext)))
+    }
+
+    // Decrypt contents of the file at `file` relative to the app directory, failing if they have
+    // been modified or moved from another file
+    pub fn decrypt(&self, contents: &str, file: &Path) -> Result<String, Error> {
+        let name = associated_data(file);
+        let (hex, aad) = match contents.trim_end().strip_prefix(MAGIC) {
+            Some(hex) => (Some(hex), name.as_str()),
+            None if self.legacy => (contents.trim_end().strip_prefix(LEGACY_MAGIC), ""),
+            None => (None, ""),
+        };
+        let bytes = hex
+            .and_then(from_hex)
+            .filter(|bytes| bytes.len() >= NONCE_LENGTH)
+            .ok_or_else(|| Error::Corrupted(format!("`{}` is not encrypted", name)))?;
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+        let plaintext = self.cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: aad.as_bytes() })
+            .map_err(|_| Error::Encryption(format!("Unable to decrypt `{}`: wrong passphrase, modified file or moved file", name)))?;
+
+        String::from_utf8(plaintext)
+            .map_err(|_| Error::Corrupted(format!("`{}` is not UTF-8 after decryption", name)))
+    }
+}
+
+pub fn is_encrypted(contents: &str) -> bool {
+    contents.starts_with(MAGIC) || contents.starts_with(LEGACY_MAGIC)
+}
+
+pub fn is_enabled(directory: &str) -> bool {
+    Path::new(directory).join(ENCRYPTION_FILENAME).exists()
+}
+
+// Passphrase from the environment variable, or prompted without echo
+fn read_passphrase(prompt: &str) -> Result<String, Error> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        if passphrase.is_empty() {
+            return Err(Error::Encryption(format!("`{}` must not be empty", PASSPHRASE_ENV)));
+        }
+        return Ok(passphrase);
+    }
+
+    rpassword::prompt_password(prompt)
+        .map_err(|err| Error::io("Unable to read passphrase".to_string(), err))
+}
+
+// Ask a new passphrase twice
+fn read_new_passphrase() -> Result<String, Error> {
+    if env::var_os(PASSPHRASE_ENV).is_some() {
+        return read_passphrase("");
+    }
+
+    let passphrase = read_passphrase("New passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(Error::Encryption("Passphrase must not be empty".to_string()));
+    }
+    if read_passphrase("Confirm passphrase: ")? != passphrase {
+        return Err(Error::Encryption("Passphrases do not match".to_string()));
+    }
+
+    Ok(passphrase)
+}
+
+fn read_key_file(directory: &str) -> Result<KeyFile, Error> {
+    let path = Path::new(directory).join(ENCRYPTION_FILENAME);
+    let contents = read_file(&path)
+        .map_err(|err| Error::io(format!("Unable to read `{}`", path.to_string_lossy()), err))?;
+
+    toml::from_str(&contents)
+        .map_err(|err| Error::Corrupted(format!("Unable to parse `{}`: {}", path.to_string_lossy(), err)))
+}
+
+// Derive the key of the encrypted diary, prompting the passphrase once per process
+pub fn unlock(directory: &str) -> Result<Cipher, Error> {
+    let key_file = read_key_file(directory)?;
+
+    let passphrase = match PASSPHRASE.get() {
+        Some(passphrase) => passphrase.clone(),
+        None => read_passphrase("Passphrase: ")?,
+    };
+
+    let cipher = Cipher::derive(&passphrase, &key_file)?;
+    cipher.decrypt(&key_file.check, Path::new(ENCRYPTION_FILENAME))
+        .map_err(|_| Error::Encryption("Wrong passphrase".to_string()))?;
+
+    let _ = PASSPHRASE.set(passphrase);
+    Ok(cipher)
+}
+
+// Page files, trash files and revision files including revisions in the trash, relative to the app directory
+fn page_files(directory: &str) -> Result<Vec<String>, Error> {
+    let mut dirs = vec![PAGES_DIR.to_string(), TRASH_DIR.to_string()];
+    for revisions_dir in &[REVISIONS_DIR.to_string(), format!("{}/{}", TRASH_DIR, REVISIONS_DIR)] {
+        let path = Path::new(directory).join(revisions_dir);
+        if !path.exists() {
+            continue;
+        }
+
+        let entries = fs::read_dir(&path)
+            .map_err(|err| Error::io(format!("Unable to list files in directory `{}`", path.to_string_lossy()), err))?;
+        for entry in entries.flatten() {
+            dirs.push(format!("{}/{}", revisions_dir, entry.file_name().to_string_lossy()));
+        }
+    }
+
+    let mut files = Vec::new();
+    for dir in &dirs {
+        let path = Path::new(directory).join(dir);
+        if !path.is_dir() {
+            continue;
+        }
+
+        let entries = fs::read_dir(&path)
+            .map_err(|err| Error::io(format!("Unable to list files in directory `{}`", path.to_string_lossy()), err))?;
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            if entry_path.extension().and_then(|ext| ext.to_str()) == Some(PAGE_EXTENSION) {
+                files.push(format!("{}/{}", dir, entry.file_name().to_string_lossy()));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+// Rewrite all page files with `convert` in one journal, together with `finish`
+fn convert_files<F, G>(directory: &str, convert: F, finish: G) -> Result<usize, Error>
+    where F: Fn(&str, &Path) -> Result<String, Error>, G: FnOnce(&mut Journal)
+{
+    // Edits are plain text which cannot be converted. They would be left behind in the app directory
+    // as the directory of edits changes with encryption.
+    for dir in &[Path::new(directory).to_path_buf(), edit_dir(directory)?] {
+        if fs::read_dir(dir.join(RECOVERY_DIR)).map(|mut entries| entries.next().is_some()).unwrap_or(false) {
+            return Err(Error::Encryption("There are unsaved edits. use `diary recover` first".to_string()));
+        }
+
+        let edit_file = fs::read_dir(dir).into_iter().flatten().flatten()
+            .find(|entry| entry.file_name().to_string_lossy().starts_with(TEMPORARY_FILE_TO_EDIT));
+        if let Some(entry) = edit_file {
+            return Err(Error::Encryption(format!("There is an unsaved edit `{}`. recover or discard it first", entry.path().to_string_lossy())));
+        }
+    }
+
+    let mut journal = Journal::new(directory);
+    let files = page_files(directory)?;
+    for file in &files {
+        let path = Path::new(directory).join(file);
+        let contents = read_file(&path)
+            .map_err(|err| Error::io(format!("Unable to read page file `{}`", path.to_string_lossy()), err))?;
+        journal.write(Path::new(file).to_path_buf(), convert(&contents, Path::new(file))?);
+    }
+
+    finish(&mut journal);
+    journal.commit()?;
+
+    Ok(files.len())
+}
+
+// Encrypt all pages of the diary with a new passphrase. Returns the number of converted files.
+// A diary encrypted by older versions is encrypted again to bind files to their paths.
+pub fn encrypt(directory: &str) -> Result<usize, Error> {
+    if is_enabled(directory) {
+        if read_key_file(directory)?.version < KEY_FILE_VERSION {
+            return upgrade(directory);
+        }
+        return Err(Error::AlreadyExists("The diary is already encrypted".to_string()));
+    }
+
+    // Check all pages can be read before converting
+    let store = FsStore::new(directory);
+    for id in store.ids()? {
+        store.get(&id)?;
+    }
+
+    let passphrase = read_new_passphrase()?;
+
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let params = Params::default();
+    let key_file = KeyFile {
+        version: KEY_FILE_VERSION,
+        salt: to_hex(&salt),
+        memory_cost: params.m_cost(),
+        time_cost: params.t_cost(),
+        parallelism: params.p_cost(),
+        check: String::new(),
+    };
+
+    let cipher = Cipher::derive(&passphrase, &key_file)?;
+    let key_file_str = key_file_contents(&cipher, key_file)?;
+
+    convert_files(directory, |contents, file| {
+        if is_encrypted(contents) {
+            return Err(Error::Corrupted(format!("`{}` is already encrypted", file.to_string_lossy())));
+        }
+        cipher.encrypt(contents, file)
+    }, |journal| {
+        journal.write(Path::new(ENCRYPTION_FILENAME).to_path_buf(), key_file_str);
+        // The search index contains words of pages
+        journal.remove(Path::new(INDEX_FILENAME).to_path_buf());
+        journal.remove(Path::new(INDEX_LOG_FILENAME).to_path_buf());
+    })
+}
+
+// Encrypt all files of the diary encrypted by an older version again with the same key
+fn upgrade(directory: &str) -> Result<usize, Error> {
+    let cipher = unlock(directory)?;
+    let mut key_file = read_key_file(directory)?;
+    key_file.version = KEY_FILE_VERSION;
+    let key_file_str = key_file_contents(&cipher, key_file)?;
+
+    convert_files(directory, |contents, file| cipher.encrypt(&cipher.decrypt(contents, file)?, file), |journal| {
+        journal.write(Path::new(ENCRYPTION_FILENAME).to_path_buf(), key_file_str);
+    })
+}
+
+// Contents of the key file with `CHECK_TEXT` encrypted by the cipher
+fn key_file_contents(cipher: &Cipher, key_file: KeyFile) -> Result<String, Error> {
+    let mut key_file = key_file;
+    key_file.check = cipher.encrypt(CHECK_TEXT, Path::new(ENCRYPTION_FILENAME))?.trim_end().to_string();
+
+    toml::to_string(&key_file)
+        .map_err(|err| Error::Parse(format!("Unable to serialize `{}`: {}", ENCRYPTION_FILENAME, err)))
+}
+
+// Decrypt all pages of the diary and disable encryption. Returns the number of converted files.
+pub fn decrypt(directory: &str) -> Result<usize, Error> {
+    if !is_enabled(directory) {
+        return Err(Error::NotFound("The diary is not encrypted".to_string()));
+    }
+
+    let cipher = unlock(directory)?;
+    convert_files(directory, |contents, file| cipher.decrypt(contents, file), |journal| {
+        journal.remove(Path::new(ENCRYPTION_FILENAME).to_path_buf());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use store::Change;
+    use utils::{new_page, write_file, HEAD_FILENAME};
+
+    fn test_cipher(passphrase: &str, version: u32) -> Cipher {
+        let key_file = KeyFile {
+            version,
+            salt: to_hex(&[1u8; SALT_LENGTH]),
+            memory_cost: 64,
+            time_cost: 1,
+            parallelism: 1,
+            check: String::new(),
+        };
+
+        Cipher::derive(passphrase, &key_file).unwrap()
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let cipher = test_cipher("secret", KEY_FILE_VERSION);
+        let file = Path::new("pages").join("a.page");
+        let encrypted = cipher.encrypt("---\ntitle = \"a\"\n---\ntext", &file).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("text"));
+        assert_eq!(cipher.decrypt(&encrypted, &file).unwrap(), "---\ntitle = \"a\"\n---\ntext");
+
+        // Modified contents, contents moved from another file and a wrong passphrase are rejected
+        let mut modified = encrypted.trim_end().to_string();
+        let last = if modified.ends_with('0') { "1" } else { "0" };
+        modified.replace_range(modified.len() - 1.., last);
+        assert!(cipher.decrypt(&modified, &file).is_err());
+        assert!(cipher.decrypt(&encrypted, Path::new("trash/a.page")).is_err());
+
+        assert!(test_cipher("wrong", KEY_FILE_VERSION).decrypt(&encrypted, &file).is_err());
+
+        // Contents of older versions are accepted only by diaries of older versions
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.cipher.encrypt(&nonce, "old".as_bytes()).unwrap();
+        let legacy = format!("{}{}{}\n", LEGACY_MAGIC, to_hex(&nonce), to_hex(&ciphertext));
+        assert!(is_encrypted(&legacy));
+        assert!(cipher.decrypt(&legacy, &file).is_err());
+        assert_eq!(test_cipher("secret", 1).decrypt(&legacy, &file).unwrap(), "old");
+    }
+
+    #[test]
+    fn moved_files_are_rejected() {
+        let directory = env::temp_dir().join(format!("diary-crypto-test-{}", process::id()));
+        fs::create_dir_all(directory.join(PAGES_DIR)).unwrap();
+        write_file(&directory.join(HEAD_FILENAME), "NULL").unwrap();
+
+        let mut store = FsStore::with_cipher(directory.to_str().unwrap(), test_cipher("secret", KEY_FILE_VERSION));
+        store.insert(new_page("a")).unwrap();
+        store.insert(new_page("b")).unwrap();
+        store.apply(vec![Change::PutRevision(1, new_page("a"))]).unwrap();
+
+        // Renamed and trashed files are encrypted again for their new paths
+        store.rename("a", "aa").unwrap();
+        assert_eq!(store.get("aa").unwrap().id, "aa");
+        assert_eq!(store.get_revision("aa", 1).unwrap().id, "aa");
+        store.delete("b", true).unwrap();
+
+        let trashed = read_file(&directory.join(TRASH_DIR).join("b.page")).unwrap();
+        write_file(&directory.join(PAGES_DIR).join("b.page"), &trashed).unwrap();
+        assert!(store.get("b").is_err());
+
+        let revision = read_file(&directory.join(REVISIONS_DIR).join("aa").join("1.page")).unwrap();
+        write_file(&directory.join(PAGES_DIR).join("aa.page"), &revision).unwrap();
+        assert!(store.get("aa").is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use chrono::{Duration, Local, Months, NaiveDate};
+
+use error::Error;
+use page::Page;
+
+// Format of ids of diary pages
+pub const DIARY_ID_FORMAT: &str = "%Y-%m-%d";
+
+// Date of the page. The date in the id for diary pages, otherwise the created date.
+pub fn page_date(page: &Page) -> NaiveDate {
+    NaiveDate::parse_from_str(&page.id, DIARY_ID_FORMAT)
+        .unwrap_or_else(|_| page.header.created.with_timezone(&Local).date_naive())
+}
+
+pub fn parse_date(s: &str) -> Result<NaiveDate, Error> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|err| Error::Parse(format!("Invalid date `{}`: {}. use YYYY-MM-DD", s, err)))
+}
+
+// Inclusive range of dates. `None` is unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DateRange {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn new(since: Option<NaiveDate>, until: Option<NaiveDate>) -> DateRange {
+        DateRange { since, until }
+    }
+
+    // Range of the month like `2018-05`
+    pub fn month(s: &str) -> Result<DateRange, Error> {
+        let first = NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d")
+            .map_err(|err| Error::Parse(format!("Invalid month `{}`: {}. use YYYY-MM", s, err)))?;
+        let last = first + Months::new(1) - Duration::days(1);
+
+        Ok(DateRange::new(Some(first), Some(last)))
+    }
+
+    // Range of the year like `2018`
+    pub fn year(s: &str) -> Result<DateRange, Error> {
+        let year = s.parse::<i32>().ok()
+            .filter(|year| NaiveDate::from_ymd_opt(*year, 1, 1).is_some())
+            .ok_or_else(|| Error::Parse(format!("Invalid year `{}`. use YYYY", s)))?;
+
+        Ok(DateRange::new(NaiveDate::from_ymd_opt(year, 1, 1), NaiveDate::from_ymd_opt(year, 12, 31)))
+    }
+
+    // Range from the period like `3d`, `2w`, `1m` or `1y` ago until the date
+    pub fn last(s: &str, today: NaiveDate) -> Result<DateRange, Error> {
+        let invalid = || Error::Parse(format!("Invalid period `{}`. use a number followed by d, w, m or y like `2w`", s));
+
+        let (count, unit) = s.split_at(s.len() - s.chars().last().map(|c| c.len_utf8()).unwrap_or(0));
+        let count = count.parse::<u32>().map_err(|_| invalid())?;
+        let since = match unit {
+            "d" => today.checked_sub_signed(Duration::days(count.into())),
+            "w" => today.checked_sub_signed(Duration::weeks(count.into())),
+            "m" => today.checked_sub_months(Months::new(count)),
+            "y" => count.checked_mul(12).and_then(|months| today.checked_sub_months(Months::new(months))),
+            _ => return Err(invalid()),
+        }.ok_or_else(invalid)?;
+
+        Ok(DateRange::new(Some(since), Some(today)))
+    }
+
+    // Range included in both ranges
+    pub fn intersect(&self, other: &DateRange) -> DateRange {
+        DateRange {
+            since: self.since.into_iter().chain(other.since).max(),
+            until: self.until.into_iter().chain(other.until).min(),
+        }
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.since.map(|since| since <= date).unwrap_or(true) && self.until.map(|until| date <= until).unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ranges() {
+        let date = |s| parse_date(s).unwrap();
+
+        assert_eq!(DateRange::month("2016-02").unwrap(), DateRange::new(Some(date("2016-02-01")), Some(date("2016-02-29"))));
+        assert_eq!(DateRange::year("2018").unwrap(), DateRange::new(Some(date("2018-01-01")), Some(date("2018-12-31"))));
+        assert_eq!(DateRange::last("2w", date("2018-03-15")).unwrap().since, Some(date("2018-03-01")));
+        assert_eq!(DateRange::last("1m", date("2018-03-31")).unwrap().since, Some(date("2018-02-28")));
+        assert_eq!(DateRange::last("1y", date("2020-02-29")).unwrap().since, Some(date("2019-02-28")));
+        assert_eq!(DateRange::last("4y", date("2020-02-29")).unwrap().since, Some(date("2016-02-29")));
+        assert!(DateRange::last("2x", date("2018-03-15")).is_err());
+        assert!(DateRange::month("2018-13").is_err());
+
+        let range = DateRange::year("2018").unwrap().intersect(&DateRange::new(Some(date("2018-06-01")), None));
+        assert!(range.contains(date("2018-12-31")));
+        assert!(!range.contains(date("2018-05-31")));
+    }
+}
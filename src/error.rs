@@ -0,0 +1,124 @@
+use std::io;
+
+use rusqlite;
+
+use page::PageError;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "{}: {}", message, error)]
+    Io {
+        message: String,
+        #[cause] error: io::Error,
+    },
+
+    #[fail(display = "{}: {}", message, error)]
+    Database {
+        message: String,
+        #[cause] error: rusqlite::Error,
+    },
+
+    #[fail(display = "{}", _0)]
+    Page(#[cause] PageError),
+
+    #[fail(display = "{}", _0)]
+    Parse(String),
+
+    #[fail(display = "Invalid ID `{}`: {}", id, reason)]
+    InvalidId {
+        id: String,
+        reason: String,
+    },
+
+    #[fail(display = "{}", _0)]
+    NotFound(String),
+
+    #[fail(display = "{}", _0)]
+    AlreadyExists(String),
+
+    #[fail(display = "{}", _0)]
+    Editor(String),
+
+    #[fail(display = "{}", _0)]
+    Locked(String),
+
+    #[fail(display = "{}", _0)]
+    Corrupted(String),
+
+    #[fail(display = "{}", _0)]
+    Encryption(String),
+
+    #[fail(display = "{}", _0)]
+    Git(String),
+}
+
+impl Error {
+    pub fn io(message: String, error: io::Error) -> Self {
+        Error::Io { message, error }
+    }
+
+    pub fn database(message: String, error: rusqlite::Error) -> Self {
+        Error::Database { message, error }
+    }
+
+    // Exit code of the process. 1 is used by clap for usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io { .. } => 2,
+            Error::Page(_) | Error::Parse(_) => 3,
+            Error::InvalidId { .. } => 4,
+            Error::NotFound(_) => 5,
+            Error::AlreadyExists(_) => 6,
+            Error::Editor(_) => 7,
+            Error::Locked(_) => 8,
+            Error::Corrupted(_) => 9,
+            Error::Database { .. } => 10,
+            Error::Encryption(_) => 11,
+            Error::Git(_) => 12,
+        }
+    }
+}
+
+impl From<PageError> for Error {
+    fn from(error: PageError) -> Self {
+        Error::Page(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use page::Page;
+    use store::{MemoryStore, Store};
+    use utils::{is_valid_id, new_page};
+
+    #[test]
+    fn exit_codes() {
+        let mut store = MemoryStore::new();
+        store.insert(new_page("2018-05-01")).unwrap();
+
+        let errors = vec![
+            (Error::io("Unable to read".to_string(), io::Error::new(io::ErrorKind::NotFound, "failed")), 2),
+            (Error::from(Page::from_str("no header", "2018-05-01").unwrap_err()), 3),
+            (Error::Parse("bad".to_string()), 3),
+            (is_valid_id("../2018-05-01").unwrap_err(), 4),
+            (store.get("2018-05-02").unwrap_err(), 5),
+            (store.insert(new_page("2018-05-01")).unwrap_err(), 6),
+            (Error::Editor("failed".to_string()), 7),
+            (Error::Locked("locked".to_string()), 8),
+            (Error::Corrupted("broken".to_string()), 9),
+            (Error::database("Unable to query".to_string(), rusqlite::Error::QueryReturnedNoRows), 10),
+            (Error::Encryption("wrong passphrase".to_string()), 11),
+            (Error::Git("failed".to_string()), 12),
+        ];
+        for (error, code) in &errors {
+            assert_eq!(error.exit_code(), *code, "{}", error);
+        }
+
+        // 0 is success and 1 is used by clap for usage errors
+        let codes: HashSet<i32> = errors.iter().map(|(error, _)| error.exit_code()).collect();
+        assert_eq!(codes.len(), 11);
+        assert!(codes.iter().all(|&code| code > 1));
+    }
+}
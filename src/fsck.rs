@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use error::Error;
+use page::Page;
+use store::{Change, Store};
+
+#[derive(Debug)]
+pub enum Problem {
+    BrokenPage { id: String, error: Error },
+    DanglingHead { head: String },
+    DanglingPrev { id: String, prev: String },
+    DanglingNext { id: String, next: String },
+    PrevMismatch { id: String, prev: String, back: String },
+    NextMismatch { id: String, next: String, back: String },
+    HeadHasNext { id: String, next: String },
+    Cycle { id: String },
+    Orphan { id: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::BrokenPage { id, error } => write!(f, "`{}` is broken: {}", id, error),
+            Problem::DanglingHead { head } => write!(f, "HEAD points to `{}` which does not exist", head),
+            Problem::DanglingPrev { id, prev } => write!(f, "`{}` points to `{}` as prev which does not exist", id, prev),
+            Problem::DanglingNext { id, next } => write!(f, "`{}` points to `{}` as next which does not exist", id, next),
+            Problem::PrevMismatch { id, prev, back } => write!(f, "`{}` points to `{}` as prev, but its next is `{}`", id, prev, back),
+            Problem::NextMismatch { id, next, back } => write!(f, "`{}` points to `{}` as next, but its prev is `{}`", id, next, back),
+            Problem::HeadHasNext { id, next } => write!(f, "HEAD page `{}` points to `{}` as next", id, next),
+            Problem::Cycle { id } => write!(f, "the chain loops back to `{}`", id),
+            Problem::Orphan { id } => write!(f, "`{}` is not reachable from HEAD", id),
+        }
+    }
+}
+
+// Load all pages in the store, collecting unparsable ones as problems
+fn load_pages(store: &dyn Store, problems: &mut Vec<Problem>) -> Result<HashMap<String, Page>, Error> {
+    let mut pages = HashMap::new();
+    for id in store.ids()? {
+        match store.get(&id) {
+            Ok(page) => { pages.insert(id, page); },
+            Err(error) => problems.push(Problem::BrokenPage { id, error }),
+        }
+    }
+
+    Ok(pages)
+}
+
+pub fn check(store: &dyn Store) -> Result<Vec<Problem>, Error> {
+    let mut problems = Vec::new();
+    let pages = load_pages(store, &mut problems)?;
+    let head_id = store.head()?;
+
+    // Links to broken pages are not dangling
+    let broken: HashSet<String> = problems.iter().filter_map(|problem| match problem {
+        Problem::BrokenPage { id, .. } => Some(id.clone()),
+        _ => None,
+    }).collect();
+
+    // Check links of every page
+    let mut ids: Vec<&String> = pages.keys().collect();
+    ids.sort();
+    for id in ids {
+        let page = &pages[id];
+
+        if page.header.prev != "NULL" {
+            match pages.get(&page.header.prev) {
+                Some(prev) if prev.header.next != *id => problems.push(Problem::PrevMismatch {
+                    id: id.clone(),
+                    prev: prev.id.clone(),
+                    back: prev.header.next.clone(),
+                }),
+                Some(_) => {},
+                None if !broken.contains(&page.header.prev) => problems.push(Problem::DanglingPrev { id: id.clone(), prev: page.header.prev.clone() }),
+                None => {},
+            }
+        }
+
+        if page.header.next != "NULL" {
+            match pages.get(&page.header.next) {
+                Some(next) if next.header.prev != *id => problems.push(Problem::NextMismatch {
+                    id: id.clone(),
+                    next: next.id.clone(),
+                    back: next.header.prev.clone(),
+                }),
+                Some(_) => {},
+                None if !broken.contains(&page.header.next) => problems.push(Problem::DanglingNext { id: id.clone(), next: page.header.next.clone() }),
+                None => {},
+            }
+        }
+    }
+
+    // Walk the chain from HEAD
+    let mut reachable = HashSet::new();
+    if head_id != "NULL" {
+        match pages.get(&head_id) {
+            // A dangling next is reported above
+            Some(head) if head.header.next != "NULL" && (pages.contains_key(&head.header.next) || broken.contains(&head.header.next)) => problems.push(Problem::HeadHasNext {
+                id: head_id.clone(),
+                next: head.header.next.clone(),
+            }),
+            Some(_) => {},
+            None if !broken.contains(&head_id) => problems.push(Problem::DanglingHead { head: head_id.clone() }),
+            None => {},
+        }
+    }
+
+    let mut prev_id = head_id;
+    while let Some(page) = pages.get(&prev_id) {
+        if !reachable.insert(prev_id.clone()) {
+            problems.push(Problem::Cycle { id: prev_id });
+            break;
+        }
+
+        prev_id = page.header.prev.clone();
+    }
+
+    let mut orphans: Vec<&String> = pages.keys().filter(|id| !reachable.contains(*id)).collect();
+    orphans.sort();
+    for id in orphans {
+        problems.push(Problem::Orphan { id: id.clone() });
+    }
+
+    Ok(problems)
+}
+
+// Changes linking the pages in order of created time and setting the head.
+// Writes pages whose links have changed and pages for which `write` returns true.
+pub fn relink<F: Fn(&Page) -> bool>(pages: Vec<Page>, write: F) -> Vec<Change> {
+    let mut pages = pages;
+    pages.sort_by(|a, b| a.header.created.cmp(&b.header.created).then_with(|| a.id.cmp(&b.id)));
+
+    let mut changes = Vec::new();
+    let ids: Vec<String> = pages.iter().map(|page| page.id.clone()).collect();
+    for (i, mut page) in pages.into_iter().enumerate() {
+        let prev = if i == 0 { "NULL".to_string() } else { ids[i - 1].clone() };
+        let next = ids.get(i + 1).cloned().unwrap_or_else(|| "NULL".to_string());
+
+        if page.header.prev != prev || page.header.next != next || write(&page) {
+            page.header.prev = prev;
+            page.header.next = next;
+            changes.push(Change::Put(page));
+        }
+    }
+
+    let head_id = ids.last().map(|id| id.as_str()).unwrap_or("NULL");
+    changes.push(Change::SetHead(head_id.to_string()));
+
+    changes
+}
+
+// Rebuild the chain from all readable pages ordered by created time and rewrite HEAD.
+// Returns the number of pages in the rebuilt chain.
+pub fn repair(store: &mut dyn Store) -> Result<usize, Error> {
+    let mut problems = Vec::new();
+    let pages = load_pages(store, &mut problems)?;
+    let count = pages.len();
+
+    store.apply(relink(pages.into_values().collect(), |_| false))?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+    use store::MemoryStore;
+    use utils::new_page;
+
+    // Store with pages of the links `(id, prev, next)` created in the order, without checking them
+    fn store_with(head: &str, links: &[(&str, &str, &str)]) -> MemoryStore {
+        let mut changes = vec![Change::SetHead(head.to_string())];
+        for (i, &(id, prev, next)) in links.iter().enumerate() {
+            let mut page = new_page(id);
+            page.header.created = Utc.with_ymd_and_hms(2018, 5, 1, 0, 0, 0).unwrap() + Duration::minutes(i as i64);
+            page.header.prev = prev.to_string();
+            page.header.next = next.to_string();
+            changes.push(Change::Put(page));
+        }
+
+        let mut store = MemoryStore::new();
+        store.apply(changes).unwrap();
+        store
+    }
+
+    fn problems(store: &dyn Store) -> Vec<String> {
+        check(store).unwrap().iter().map(|problem| problem.to_string()).collect()
+    }
+
+    #[test]
+    fn check_links() {
+        assert!(problems(&store_with("b", &[("a", "NULL", "b"), ("b", "a", "NULL")])).is_empty());
+
+        // Dangling links, reported once for the head page
+        assert_eq!(problems(&store_with("x", &[("a", "NULL", "NULL")])), vec![
+            "HEAD points to `x` which does not exist",
+            "`a` is not reachable from HEAD",
+        ]);
+        assert_eq!(problems(&store_with("b", &[("a", "y", "b"), ("b", "a", "z")])), vec![
+            "`a` points to `y` as prev which does not exist",
+            "`b` points to `z` as next which does not exist",
+        ]);
+        assert_eq!(problems(&store_with("b", &[("a", "NULL", "b"), ("b", "a", "a")])), vec![
+            "`b` points to `a` as next, but its prev is `NULL`",
+            "HEAD page `b` points to `a` as next",
+        ]);
+
+        // Mismatched links
+        assert_eq!(problems(&store_with("c", &[("a", "NULL", "c"), ("b", "a", "c"), ("c", "b", "NULL")])), vec![
+            "`a` points to `c` as next, but its prev is `b`",
+            "`b` points to `a` as prev, but its next is `c`",
+        ]);
+
+        // A cycle and pages outside of the chain
+        assert_eq!(problems(&store_with("b", &[("a", "b", "b"), ("b", "a", "NULL"), ("c", "NULL", "NULL")])), vec![
+            "`a` points to `b` as prev, but its next is `NULL`",
+            "the chain loops back to `b`",
+            "`c` is not reachable from HEAD",
+        ]);
+    }
+
+    #[test]
+    fn repair_orders_by_created_time() {
+        let mut store = store_with("a", &[("c", "a", "NULL"), ("a", "x", "b"), ("b", "NULL", "NULL")]);
+        assert_eq!(repair(&mut store).unwrap(), 3);
+        assert!(problems(&store).is_empty());
+
+        let ids: Vec<String> = (&store as &dyn Store).iter().unwrap().map(|page| page.unwrap().id).collect();
+        assert_eq!(ids, vec!["b", "a", "c"]);
+        assert_eq!(store.get("c").unwrap().header.prev, "NULL");
+        assert_eq!(store.get("a").unwrap().header.next, "b");
+    }
+}
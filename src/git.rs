@@ -0,0 +1,386 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use config::{Config, Storage};
+use crypto;
+use error::Error;
+use fsck;
+use journal::{JOURNAL_FILENAME, TEMPORARY_EXTENSION};
+use lock::LOCK_FILENAME;
+use index::{INDEX_FILENAME, INDEX_LOG_FILENAME};
+use page::Page;
+use sqlite::DATABASE_FILENAME;
+use store::FsStore;
+use utils::{read_file, write_file, HEAD_FILENAME, PAGES_DIR, PAGE_EXTENSION, RECOVERY_DIR, TEMPORARY_FILE_TO_EDIT, TRASH_DIR};
+
+// Remote name used by `diary sync`
+pub const REMOTE_NAME: &str = "origin";
+
+// Identity used for commits when the user has not configured one
+const DEFAULT_NAME: &str = "diary";
+const DEFAULT_EMAIL: &str = "diary@localhost";
+
+fn git(directory: &str) -> Command {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(directory).arg("--no-pager");
+    command
+}
+
+fn output(command: &mut Command) -> Result<Output, Error> {
+    command.stdin(Stdio::null()).output()
+        .map_err(|err| Error::io(String::from("Unable to execute `git`"), err))
+}
+
+// Run git and return its stdout, failing with its stderr
+fn run(directory: &str, args: &[&str]) -> Result<String, Error> {
+    let output = output(git(directory).args(args))?;
+    if !output.status.success() {
+        return Err(Error::Git(format!("`git {}` failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Run git with the output shown on the terminal
+fn run_interactive(directory: &str, args: &[&str]) -> Result<(), Error> {
+    let status = git(directory).args(args).status()
+        .map_err(|err| Error::io(String::from("Unable to execute `git`"), err))?;
+    if !status.success() {
+        return Err(Error::Git(format!("`git {}` failed", args.join(" "))));
+    }
+
+    Ok(())
+}
+
+fn succeeds(directory: &str, args: &[&str]) -> Result<bool, Error> {
+    Ok(output(git(directory).args(args))?.status.success())
+}
+
+// Options setting the identity of commits if the user has not configured one
+fn identity(directory: &str) -> Result<Vec<String>, Error> {
+    if succeeds(directory, &["config", "user.email"])? {
+        return Ok(Vec::new());
+    }
+
+    Ok(vec![
+        String::from("-c"), format!("user.name={}", DEFAULT_NAME),
+        String::from("-c"), format!("user.email={}", DEFAULT_EMAIL),
+    ])
+}
+
+// Pages in the SQLite database are ignored, so commits would record nothing. Encrypted diaries are
+// not supported either, as commit messages and file names contain page ids.
+pub fn ensure_supported(directory: &str, config: &Config) -> Result<(), Error> {
+    if config.storage == Storage::Sqlite {
+        return Err(Error::Git(String::from("The git history does not support `storage = \"sqlite\"`. set `git = false` in config.toml")));
+    }
+
+    if crypto::is_enabled(directory) {
+        return Err(Error::Git(String::from("The git history does not support encrypted diaries. set `git = false` in config.toml")));
+    }
+
+    Ok(())
+}
+
+// Remove the repository with all the history
+pub fn purge(directory: &str) -> Result<(), Error> {
+    let path = Path::new(directory).join(".git");
+    fs::remove_dir_all(&path)
+        .map_err(|err| Error::io(format!("Unable to remove `{}`", path.to_string_lossy()), err))
+}
+
+// Run git with the identity, for commands creating commits
+fn run_with_identity(directory: &str, args: &[&str]) -> Result<String, Error> {
+    let identity = identity(directory)?;
+    let mut all_args: Vec<&str> = identity.iter().map(|arg| arg.as_str()).collect();
+    all_args.extend_from_slice(args);
+    run(directory, &all_args)
+}
+
+fn page_path(id: &str) -> String {
+    format!("{}/{}.{}", PAGES_DIR, id, PAGE_EXTENSION)
+}
+
+pub fn is_repository(directory: &str) -> bool {
+    Path::new(directory).join(".git").exists()
+}
+
+// Create the repository ignoring files which are not part of the diary.
+// Files added by newer versions are also ignored in repositories created before.
+pub fn init(directory: &str) -> Result<(), Error> {
+    if !is_repository(directory) {
+        run(directory, &["init", "--quiet"])?;
+    }
+
+    let ignored = [
+        "config.toml".to_string(),
+        LOCK_FILENAME.to_string(),
+        JOURNAL_FILENAME.to_string(),
+        INDEX_FILENAME.to_string(),
+        INDEX_LOG_FILENAME.to_string(),
+        format!("{}*", DATABASE_FILENAME),
+        format!("*.{}", TEMPORARY_EXTENSION),
+        TEMPORARY_FILE_TO_EDIT.to_string(),
+        format!("{}.*", TEMPORARY_FILE_TO_EDIT),
+        format!("{}/", RECOVERY_DIR),
+        format!("{}/", TRASH_DIR),
+    ];
+    let path = Path::new(directory).join(".gitignore");
+    let contents = if path.exists() {
+        read_file(&path).map_err(|err| Error::io(format!("Unable to read `{}`", path.to_string_lossy()), err))?
+    } else {
+        String::new()
+    };
+
+    let missing: Vec<&str> = ignored.iter()
+        .map(|pattern| pattern.as_str())
+        .filter(|pattern| !contents.lines().any(|line| line == *pattern))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let separator = if contents.is_empty() || contents.ends_with('\n') { "" } else { "\n" };
+    write_file(&path, &format!("{}{}{}\n", contents, separator, missing.join("\n")))
+        .map_err(|err| Error::io(format!("Unable to write `{}`", path.to_string_lossy()), err))
+}
+
+// Page id of a path in the repository
+fn path_id(path: &str) -> Option<&str> {
+    path.strip_prefix(PAGES_DIR)
+        .and_then(|path| path.strip_prefix('/'))
+        .and_then(|name| name.strip_suffix(PAGE_EXTENSION))
+        .and_then(|name| name.strip_suffix('.'))
+}
+
+// Commit message describing the output of `git diff --name-status`. The subject is the most
+// significant change, as adding or removing a page also changes links of its neighbors.
+// Other changes are listed in the body.
+fn commit_message(name_status: &str) -> String {
+    let mut changes = Vec::new();
+    for line in name_status.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let change = match (fields[0].chars().next(), fields.get(1).and_then(|path| path_id(path)), fields.get(2).and_then(|path| path_id(path))) {
+            (Some('A'), Some(id), _) => (0, format!("Add `{}`", id)),
+            (Some('R'), Some(from), Some(to)) => (1, format!("Rename `{}` to `{}`", from, to)),
+            (Some('D'), Some(id), _) => (2, format!("Remove `{}`", id)),
+            (Some('M'), Some(id), _) => (3, format!("Edit `{}`", id)),
+            _ => continue,
+        };
+        changes.push(change);
+    }
+    changes.sort();
+
+    let changes: Vec<String> = changes.into_iter().map(|(_, change)| change).collect();
+    match changes.len() {
+        0 => String::from("Update diary"),
+        1 => changes[0].clone(),
+        _ => format!("{}\n\n{}", changes[0], changes[1..].join("\n")),
+    }
+}
+
+// Commit all changes in the app directory. Does nothing if there are no changes.
+pub fn commit(directory: &str) -> Result<(), Error> {
+    init(directory)?;
+    run(directory, &["add", "--all"])?;
+
+    let name_status = run(directory, &["diff", "--cached", "--name-status", "--find-renames"])?;
+    if name_status.is_empty() {
+        return Ok(());
+    }
+
+    let message = commit_message(&name_status);
+    run_with_identity(directory, &["commit", "--quiet", "-m", &message])?;
+
+    Ok(())
+}
+
+fn ensure_repository(directory: &str) -> Result<(), Error> {
+    if !is_repository(directory) {
+        return Err(Error::NotFound(String::from("The diary has no history. set `git = true` in config.toml")));
+    }
+
+    Ok(())
+}
+
+// Show commits changing the page
+pub fn log(directory: &str, id: &str) -> Result<(), Error> {
+    ensure_repository(directory)?;
+    run_interactive(directory, &["log", "--follow", "--date=format:%Y-%m-%d %H:%M", "--format=%C(yellow)%h%C(reset) %ad %s", "--", &page_path(id)])
+}
+
+// Show changes of the page since the revision, or the last change if it is not given.
+// The revision can be a range like `HEAD~3..HEAD~1`.
+pub fn diff(directory: &str, id: &str, rev: Option<&str>) -> Result<(), Error> {
+    ensure_repository(directory)?;
+    if crypto::is_enabled(directory) {
+        return Err(Error::Encryption(String::from("Page files of encrypted diaries cannot be compared")));
+    }
+
+    let path = page_path(id);
+    match rev {
+        // Not to be taken as an option of git
+        Some(rev) if rev.starts_with('-') => Err(Error::Parse(format!("Invalid revision `{}`", rev))),
+        Some(rev) => run_interactive(directory, &["diff", rev, "--", &path]),
+        None => run_interactive(directory, &["log", "--follow", "-p", "-n", "1", "--format=%C(yellow)%h%C(reset) %ad %s", "--", &path]),
+    }
+}
+
+fn is_rebasing(directory: &str) -> bool {
+    let git_dir = Path::new(directory).join(".git");
+    git_dir.join("rebase-merge").exists() || git_dir.join("rebase-apply").exists()
+}
+
+// The page without its links, which are rewritten when the chain is rebuilt
+fn unlinked(contents: &str, id: &str) -> Option<String> {
+    let mut page = Page::from_str(contents, id).ok()?;
+    page.header.prev = String::from("NULL");
+    page.header.next = String::from("NULL");
+    page.to_str().ok()
+}
+
+// Resolve conflicts of the rebase caused by adding pages on both sides, which change HEAD and links
+// of the previous head. Returns false if contents of pages conflict.
+fn resolve_link_conflicts(directory: &str, paths: &[String]) -> Result<bool, Error> {
+    for path in paths {
+        if path != HEAD_FILENAME {
+            let id = match path_id(path) {
+                Some(id) => id,
+                None => return Ok(false),
+            };
+
+            // Both sides, which do not exist if the page is removed on one side
+            let ours = run(directory, &["show", &format!(":2:{}", path)]).ok().and_then(|contents| unlinked(&contents, id));
+            let theirs = run(directory, &["show", &format!(":3:{}", path)]).ok().and_then(|contents| unlinked(&contents, id));
+            if ours.is_none() || ours != theirs {
+                return Ok(false);
+            }
+        }
+
+        run(directory, &["checkout", "--theirs", "--", path])?;
+        run(directory, &["add", "--", path])?;
+    }
+
+    Ok(true)
+}
+
+// Pull changes from the remote with rebase and push local commits. Returns true if pages were pulled.
+// If pages were added on both sides, the chain is rebuilt in order of created time and committed.
+pub fn sync(directory: &str, remote: &str) -> Result<bool, Error> {
+    commit(directory)?;
+
+    match run(directory, &["remote", "get-url", REMOTE_NAME]) {
+        Ok(ref url) if url.trim() == remote => {},
+        Ok(_) => { run(directory, &["remote", "set-url", REMOTE_NAME, remote])?; },
+        Err(_) => { run(directory, &["remote", "add", REMOTE_NAME, remote])?; },
+    }
+
+    let branch = run(directory, &["rev-parse", "--abbrev-ref", "HEAD"])?.trim().to_string();
+    let before = run(directory, &["rev-parse", "HEAD"])?;
+
+    // The remote branch does not exist until the first push
+    let mut relink = false;
+    if succeeds(directory, &["ls-remote", "--exit-code", "--heads", REMOTE_NAME, &branch])? {
+        let mut result = run_with_identity(directory, &["pull", "--quiet", "--rebase", REMOTE_NAME, &branch]);
+        while let Err(err) = result {
+            if !is_rebasing(directory) {
+                return Err(err);
+            }
+
+            let conflicts: Vec<String> = run(directory, &["diff", "--name-only", "--diff-filter=U"])?
+                .lines().map(|path| path.to_string()).collect();
+            if conflicts.is_empty() {
+                // The local commit has nothing left to apply
+                result = run_with_identity(directory, &["rebase", "--skip"]);
+                continue;
+            }
+
+            if !resolve_link_conflicts(directory, &conflicts)? {
+                let _ = run(directory, &["rebase", "--abort"]);
+                return Err(Error::Git(format!("{}\nThe same pages were changed on both sides. The pull was cancelled and local pages are unchanged. Change them on one side and sync again", err)));
+            }
+
+            relink = true;
+            result = run_with_identity(directory, &["-c", "core.editor=true", "rebase", "--continue"]);
+        }
+    }
+
+    if relink {
+        fsck::repair(&mut FsStore::new(directory))?;
+        commit(directory)?;
+    }
+
+    run(directory, &["push", "--quiet", "--set-upstream", REMOTE_NAME, &branch])?;
+
+    Ok(run(directory, &["rev-parse", "HEAD"])? != before)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_changes() {
+        assert_eq!(commit_message("A\tpages/2018-05-01.page\nM\tHEAD\n"), "Add `2018-05-01`");
+        assert_eq!(commit_message("R100\tpages/a.page\tpages/b.page\n"), "Rename `a` to `b`");
+        assert_eq!(commit_message("M\tpages/a.page\nD\tpages/b.page\n"), "Remove `b`\n\nEdit `a`");
+        assert_eq!(commit_message("A\tENCRYPTION\n"), "Update diary");
+    }
+
+    #[test]
+    fn sync_pages_added_on_both_sides() {
+        use std::env;
+        use std::process;
+        use store::Store;
+        use utils::new_page;
+
+        let root = env::temp_dir().join(format!("diary-git-test-{}", process::id()));
+        let remote = root.join("remote.git");
+        fs::create_dir_all(&remote).unwrap();
+        run(remote.to_str().unwrap(), &["init", "--quiet", "--bare"]).unwrap();
+        let remote = remote.to_str().unwrap();
+
+        let diaries: Vec<String> = ["a", "b"].iter().map(|name| {
+            let directory = root.join(name);
+            fs::create_dir_all(directory.join(PAGES_DIR)).unwrap();
+            write_file(&directory.join(HEAD_FILENAME), "NULL").unwrap();
+            directory.to_str().unwrap().to_string()
+        }).collect();
+        let add = |directory: &str, id: &str| FsStore::new(directory).insert(new_page(id)).unwrap();
+
+        add(&diaries[0], "first");
+        assert!(!sync(&diaries[0], remote).unwrap());
+        assert!(sync(&diaries[1], remote).unwrap());
+
+        // HEAD and the next of `first` conflict
+        add(&diaries[0], "second");
+        add(&diaries[1], "third");
+        sync(&diaries[0], remote).unwrap();
+        sync(&diaries[1], remote).unwrap();
+        sync(&diaries[0], remote).unwrap();
+
+        for directory in &diaries {
+            let store = FsStore::new(directory);
+            assert!(fsck::check(&store).unwrap().is_empty());
+            assert_eq!(store.head().unwrap(), "third");
+            assert_eq!(store.get("third").unwrap().header.prev, "second");
+        }
+
+        // Contents changed on both sides are not merged
+        let edit = |directory: &str, text: &str| {
+            let mut store = FsStore::new(directory);
+            let mut page = store.get("first").unwrap();
+            page.text = text.to_string();
+            store.put(&page).unwrap();
+        };
+        edit(&diaries[0], "a");
+        edit(&diaries[1], "b");
+        sync(&diaries[0], remote).unwrap();
+        assert!(sync(&diaries[1], remote).is_err());
+        assert!(!is_rebasing(&diaries[1]));
+        assert_eq!(FsStore::new(&diaries[1]).get("first").unwrap().text, "b");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
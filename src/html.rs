@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use chrono::{Datelike, NaiveDate};
+use pulldown_cmark::{self, Event, Options, Parser, Tag};
+use pulldown_cmark::escape::escape_html;
+
+use date::page_date;
+use error::Error;
+use page::Page;
+use store::Store;
+use utils::write_file;
+
+// Directory of page files in the output directory
+pub const HTML_PAGES_DIR: &str = "pages";
+
+const STYLE: &str = "
+body { max-width: 46em; margin: 2em auto; padding: 0 1em; font-family: sans-serif; line-height: 1.6; color: #222; }
+nav { display: flex; justify-content: space-between; margin: 1em 0; }
+.meta { color: #777; font-size: 0.9em; }
+.tag { margin-right: 0.5em; }
+pre { overflow-x: auto; background: #f4f4f4; padding: 0.5em; }
+";
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    escape_html(&mut escaped, s).unwrap();
+    escaped
+}
+
+// Percent-encode a path segment of a link
+fn encode(s: &str) -> String {
+    s.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+fn page_link(page: &Page, root: &str) -> String {
+    format!("<a href=\"{}{}/{}.html\">{}</a>", root, HTML_PAGES_DIR, encode(&page.id), escape(&page.header.title))
+}
+
+fn tag_link(tag: &str, root: &str) -> String {
+    format!("<a class=\"tag\" href=\"{}tags.html#tag-{}\">#{}</a>", root, encode(tag), escape(tag))
+}
+
+// Links which run scripts when they are opened
+fn is_script_link(dest: &str) -> bool {
+    let scheme: String = dest.chars().filter(|c| !c.is_whitespace() && !c.is_control()).take(11).collect();
+    let scheme = scheme.to_lowercase();
+    scheme.starts_with("javascript:") || scheme.starts_with("vbscript:") || scheme.starts_with("data:")
+}
+
+// Raw HTML in pages is shown as text and script links are dropped, so that an exported page
+// cannot run scripts
+fn markdown(text: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS | Options::ENABLE_FOOTNOTES;
+    let events = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(Tag::Link(kind, dest, title)) if is_script_link(&dest) => Event::Start(Tag::Link(kind, "".into(), title)),
+        Event::Start(Tag::Image(kind, dest, title)) if is_script_link(&dest) => Event::Start(Tag::Image(kind, "".into(), title)),
+        event => event,
+    });
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events);
+    html
+}
+
+// Whole HTML document. `root` is the relative path to the output directory.
+fn layout(title: &str, body: &str, root: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<header><a href=\"{}index.html\">Index</a> | <a href=\"{}tags.html\">Tags</a></header>\n{}</body>\n</html>\n",
+        escape(title), STYLE, root, root, body)
+}
+
+fn render_page(page: &Page, pages: &BTreeMap<String, Page>) -> String {
+    let root = "../";
+    let mut body = String::new();
+
+    // Same as `diary show`
+    if page.header.insert_title {
+        body.push_str(&format!("<h1>{}</h1>\n", escape(&page.header.title)));
+    }
+
+    let tags: Vec<String> = page.tags().iter().map(|tag| tag_link(tag, root)).collect();
+    body.push_str(&format!("<p class=\"meta\">{}{} {}</p>\n",
+                           page_date(page),
+                           if page.header.memo { " memo" } else { "" },
+                           tags.join("")));
+
+    body.push_str(&markdown(&page.text));
+
+    // Links to older and newer pages
+    let link = |id: &str, label: &str| match pages.get(id) {
+        Some(linked) => format!("<a href=\"{}.html\">{} {}</a>", encode(&linked.id), label, escape(&linked.header.title)),
+        None => "<span></span>".to_string(),
+    };
+    body.push_str(&format!("<nav>{}{}</nav>\n", link(&page.header.prev, "&larr;"), link(&page.header.next, "&rarr;")));
+
+    layout(&page.header.title, &body, root)
+}
+
+// Pages grouped by year and month, newest first
+fn render_index(pages: &BTreeMap<String, Page>) -> String {
+    let mut months: BTreeMap<(i32, u32), Vec<(NaiveDate, &Page)>> = BTreeMap::new();
+    for page in pages.values() {
+        let date = page_date(page);
+        months.entry((date.year(), date.month())).or_default().push((date, page));
+    }
+
+    let mut body = String::from("<h1>Index</h1>\n");
+    let mut current_year = None;
+    for (&(year, month), month_pages) in months.iter_mut().rev() {
+        if current_year != Some(year) {
+            body.push_str(&format!("<h2 id=\"{}\">{}</h2>\n", year, year));
+            current_year = Some(year);
+        }
+
+        month_pages.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.1.id.cmp(&a.1.id)));
+        body.push_str(&format!("<h3 id=\"{}-{:02}\">{}-{:02}</h3>\n<ul>\n", year, month, year, month));
+        for (date, page) in month_pages.iter() {
+            body.push_str(&format!("<li>{} {}</li>\n", date, page_link(page, "")));
+        }
+        body.push_str("</ul>\n");
+    }
+
+    layout("Index", &body, "")
+}
+
+// Pages of each tag and memo pages
+fn render_tags(pages: &BTreeMap<String, Page>) -> String {
+    let mut tags: BTreeMap<String, Vec<&Page>> = BTreeMap::new();
+    let mut memos = Vec::new();
+    for page in pages.values() {
+        for tag in page.tags() {
+            tags.entry(tag).or_default().push(page);
+        }
+        if page.header.memo {
+            memos.push(page);
+        }
+    }
+
+    let list = |pages: &[&Page]| {
+        let items: Vec<String> = pages.iter().map(|page| format!("<li>{}</li>\n", page_link(page, ""))).collect();
+        format!("<ul>\n{}</ul>\n", items.concat())
+    };
+
+    let mut body = String::from("<h1>Tags</h1>\n");
+    for (tag, tag_pages) in &tags {
+        body.push_str(&format!("<h2 id=\"tag-{}\">#{}</h2>\n", encode(tag), escape(tag)));
+        body.push_str(&list(tag_pages));
+    }
+
+    body.push_str("<h2 id=\"memos\">Memos</h2>\n");
+    body.push_str(&list(&memos));
+
+    layout("Tags", &body, "")
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), Error> {
+    write_file(&path.to_path_buf(), contents)
+        .map_err(|err| Error::io(format!("Unable to write `{}`", path.to_string_lossy()), err))
+}
+
+// Render all pages into `outdir`. Returns the number of pages.
+pub fn export(store: &dyn Store, outdir: &Path) -> Result<usize, Error> {
+    let pages_dir = outdir.join(HTML_PAGES_DIR);
+    fs::create_dir_all(&pages_dir)
+        .map_err(|err| Error::io(format!("Unable to create directory `{}`", pages_dir.to_string_lossy()), err))?;
+
+    let mut pages = BTreeMap::new();
+    for id in store.ids()? {
+        let page = store.get(&id)?;
+        pages.insert(id, page);
+    }
+
+    for page in pages.values() {
+        write(&pages_dir.join(format!("{}.html", page.id)), &render_page(page, &pages))?;
+    }
+
+    write(&outdir.join("index.html"), &render_index(&pages))?;
+    write(&outdir.join("tags.html"), &render_tags(&pages))?;
+
+    Ok(pages.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::new_page;
+
+    #[test]
+    fn escape_raw_html() {
+        assert_eq!(markdown("<script>alert(1)</script>\n"), "&lt;script&gt;alert(1)&lt;/script&gt;\n");
+        assert_eq!(markdown("a <img src=x onerror=alert(1)> b\n"), "<p>a &lt;img src=x onerror=alert(1)&gt; b</p>\n");
+        assert_eq!(markdown("`<b>` **bold**\n"), "<p><code>&lt;b&gt;</code> <strong>bold</strong></p>\n");
+
+        assert_eq!(markdown("[link](javascript:alert(1))\n"), "<p><a href=\"\">link</a></p>\n");
+        assert_eq!(markdown("[link](<Java Script:alert(1)>)\n"), "<p><a href=\"\">link</a></p>\n");
+        assert_eq!(markdown("![image](data:text/html,x)\n"), "<p><img src=\"\" alt=\"image\" /></p>\n");
+        assert_eq!(markdown("[link](https://example.com/?a=1&b=2)\n"), "<p><a href=\"https://example.com/?a=1&amp;b=2\">link</a></p>\n");
+    }
+
+    #[test]
+    fn escape_page() {
+        let mut page = new_page("2018-05-01");
+        page.header.title = "<i>title</i>".to_string();
+        page.header.tags = vec!["<b>".to_string()];
+        page.text = "<iframe src=x></iframe>\n".to_string();
+
+        let html = render_page(&page, &BTreeMap::new());
+        assert!(html.contains("<title>&lt;i&gt;title&lt;/i&gt;</title>"));
+        assert!(html.contains("<h1>&lt;i&gt;title&lt;/i&gt;</h1>"));
+        assert!(html.contains("href=\"../tags.html#tag-%3Cb%3E\">#&lt;b&gt;</a>"));
+        assert!(html.contains("&lt;iframe src=x&gt;&lt;/iframe&gt;"));
+        assert!(!html.contains("<i>") && !html.contains("<b>") && !html.contains("<iframe"));
+    }
+}
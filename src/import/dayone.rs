@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use serde_json;
+
+use error::Error;
+use import::{parse_time, Entry, Importer};
+use utils::read_file;
+
+// JSON exported by Day One
+pub struct DayOneImporter;
+
+#[derive(Debug, Deserialize)]
+struct Export {
+    entries: Vec<DayOneEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DayOneEntry {
+    creation_date: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+// The first line is the title, without Markdown heading markers
+fn split_title(text: &str) -> (String, String) {
+    let text = text.trim();
+    let (first, rest) = match text.find('\n') {
+        Some(pos) => (&text[..pos], &text[pos + 1..]),
+        None => (text, ""),
+    };
+
+    (first.trim_start_matches('#').trim().to_string(), rest.trim().to_string())
+}
+
+impl Importer for DayOneImporter {
+    fn parse(&self, path: &Path) -> Result<Vec<Entry>, Error> {
+        let contents = read_file(&path.to_path_buf())
+            .map_err(|err| Error::io(format!("Unable to read `{}`", path.to_string_lossy()), err))?;
+        let export: Export = serde_json::from_str(&contents)
+            .map_err(|err| Error::Parse(format!("Invalid Day One JSON: {}", err)))?;
+
+        export.entries.into_iter().map(|entry| {
+            let created = parse_time(&entry.creation_date)
+                .ok_or_else(|| Error::Parse(format!("Invalid creationDate `{}`", entry.creation_date)))?;
+            let (title, text) = split_title(&entry.text);
+
+            Ok(Entry {
+                title,
+                created,
+                text,
+                tags: entry.tags,
+                memo_id: None,
+            })
+        }).collect()
+    }
+}
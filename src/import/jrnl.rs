@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use regex::Regex;
+
+use error::Error;
+use import::{parse_time, Entry, Importer};
+use utils::read_file;
+
+// Plain text exported by jrnl like
+//
+// [2018-08-15 05:52:11 PM] Title. The rest of the body
+// continues until the next entry. @tag
+pub struct JrnlImporter;
+
+// The title is the first sentence as jrnl does
+fn split_title(s: &str) -> (String, String) {
+    let end = s.char_indices()
+        .find(|&(i, c)| matches!(c, '.' | '?' | '!') && s[i + 1..].starts_with(' '))
+        .map(|(i, _)| i + 1);
+
+    match end {
+        Some(end) => (s[..end].to_string(), s[end..].trim().to_string()),
+        None => (s.trim().to_string(), String::new()),
+    }
+}
+
+impl Importer for JrnlImporter {
+    fn parse(&self, path: &Path) -> Result<Vec<Entry>, Error> {
+        let contents = read_file(&path.to_path_buf())
+            .map_err(|err| Error::io(format!("Unable to read `{}`", path.to_string_lossy()), err))?;
+
+        let header = Regex::new(r"^\[?(\d{4}-\d{2}-\d{2}[ T]\d{1,2}:\d{2}(?::\d{2})?(?: ?[AaPp][Mm])?)\]? (.*)$").unwrap();
+        let tag = Regex::new(r"(?:^|\s)@([\w-]+)").unwrap();
+
+        // Time, first line and the following lines of each entry
+        let mut raw_entries: Vec<(String, String, Vec<&str>)> = Vec::new();
+        for line in contents.lines() {
+            match header.captures(line) {
+                Some(captures) => raw_entries.push((captures[1].to_string(), captures[2].to_string(), Vec::new())),
+                None => match raw_entries.last_mut() {
+                    Some(entry) => entry.2.push(line),
+                    None if line.trim().is_empty() => {},
+                    None => return Err(Error::Parse(format!("Not a jrnl entry: `{}`", line))),
+                },
+            }
+        }
+
+        raw_entries.into_iter().map(|(time, first_line, lines)| {
+            let created = parse_time(&time.to_uppercase())
+                .ok_or_else(|| Error::Parse(format!("Invalid time `{}`", time)))?;
+
+            let (title, rest) = split_title(&first_line);
+            let text = format!("{}\n{}", rest, lines.join("\n")).trim().to_string();
+
+            let mut tags: Vec<String> = Vec::new();
+            for source in [&title, &text] {
+                for captures in tag.captures_iter(source) {
+                    if !tags.contains(&captures[1].to_string()) {
+                        tags.push(captures[1].to_string());
+                    }
+                }
+            }
+
+            Ok(Entry {
+                title,
+                created,
+                text,
+                tags,
+                memo_id: None,
+            })
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_title_at_sentence() {
+        assert_eq!(split_title("Went out. It was @sunny."), ("Went out.".to_string(), "It was @sunny.".to_string()));
+        assert_eq!(split_title("No sentence end"), ("No sentence end".to_string(), String::new()));
+    }
+}
@@ -0,0 +1,125 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde_yaml::{self, Value};
+
+use error::Error;
+use import::{parse_time, Entry, Importer};
+use utils::{read_file, INVALID_CHARACTERS};
+
+// Directory of Markdown files with optional YAML front matter like
+//
+// ---
+// title: Title
+// date: 2018-08-15 17:52
+// tags: [work]
+// ---
+//
+// Files without a date are imported as memos named after the file.
+pub struct MarkdownImporter;
+
+// Split into the front matter and the body
+fn split_front_matter(contents: &str) -> (Option<&str>, &str) {
+    let rest = match contents.strip_prefix("---\n").or_else(|| contents.strip_prefix("---\r\n")) {
+        Some(rest) => rest,
+        None => return (None, contents),
+    };
+
+    for end in &["\n---\n", "\n---\r\n", "\n...\n"] {
+        if let Some(pos) = rest.find(end) {
+            return (Some(&rest[..pos]), &rest[pos + end.len()..]);
+        }
+    }
+    if let Some(front_matter) = rest.strip_suffix("\n---") {
+        return (Some(front_matter), "");
+    }
+
+    (None, contents)
+}
+
+fn string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_file(path: &Path) -> Result<Entry, Error> {
+    let contents = read_file(&path.to_path_buf())
+        .map_err(|err| Error::io(format!("Unable to read `{}`", path.to_string_lossy()), err))?;
+    let (front_matter, body) = split_front_matter(&contents);
+
+    let front_matter: Value = match front_matter {
+        Some(front_matter) => serde_yaml::from_str(front_matter)
+            .map_err(|err| Error::Parse(format!("Invalid front matter in `{}`: {}", path.to_string_lossy(), err)))?,
+        None => Value::Null,
+    };
+
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let title = front_matter.get("title").and_then(string).unwrap_or_else(|| stem.clone());
+
+    let date = ["date", "created"].iter()
+        .filter_map(|key| front_matter.get(*key).and_then(string))
+        .next();
+    let created = match date {
+        Some(date) => Some(parse_time(&date)
+            .ok_or_else(|| Error::Parse(format!("Invalid date `{}` in `{}`", date, path.to_string_lossy())))?),
+        None => None,
+    };
+
+    let tags = match front_matter.get("tags") {
+        Some(Value::Sequence(tags)) => tags.iter().filter_map(string).collect(),
+        Some(Value::String(tags)) => tags.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| tag.to_string())
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    // Memos named after the file, created when the file was modified
+    let (created, memo_id) = match created {
+        Some(created) => (created, None),
+        None => {
+            let modified = fs::metadata(path).and_then(|metadata| metadata.modified())
+                .map(DateTime::<Utc>::from)
+                .unwrap_or_else(|_| Utc::now());
+            let id = INVALID_CHARACTERS.iter().fold(stem, |id, c| id.replace(c, "_"));
+            (modified, Some(id))
+        },
+    };
+
+    Ok(Entry {
+        title,
+        created,
+        text: body.trim().to_string(),
+        tags,
+        memo_id,
+    })
+}
+
+impl Importer for MarkdownImporter {
+    fn parse(&self, path: &Path) -> Result<Vec<Entry>, Error> {
+        // A single file is also accepted
+        if path.is_file() {
+            return Ok(vec![parse_file(path)?]);
+        }
+
+        let entries = fs::read_dir(path)
+            .map_err(|err| Error::io(format!("Unable to list files in directory `{}`", path.to_string_lossy()), err))?;
+
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for entry in entries {
+            let entry_path = entry
+                .map_err(|err| Error::io(format!("Unable to list files in directory `{}`", path.to_string_lossy()), err))?
+                .path();
+            if matches!(entry_path.extension().and_then(|ext| ext.to_str()), Some("md") | Some("markdown")) {
+                paths.push(entry_path);
+            }
+        }
+        paths.sort();
+
+        paths.iter().map(|path| parse_file(path)).collect()
+    }
+}
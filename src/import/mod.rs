@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use date::DIARY_ID_FORMAT;
+use error::Error;
+use fsck::relink;
+use page::{Page, PageHeader};
+use store::Store;
+use utils::is_valid_id;
+
+pub mod markdown;
+pub mod dayone;
+pub mod jrnl;
+
+// Entry read from another journaling tool
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub title: String,
+    pub created: DateTime<Utc>,
+    pub text: String,
+    pub tags: Vec<String>,
+    // Id to use for entries without a date, like a file name
+    pub memo_id: Option<String>,
+}
+
+// Parser of files exported by another journaling tool
+pub trait Importer {
+    fn parse(&self, path: &Path) -> Result<Vec<Entry>, Error>;
+}
+
+// Importer of the format name
+pub fn importer(name: &str) -> Option<Box<dyn Importer>> {
+    match name {
+        "markdown" => Some(Box::new(markdown::MarkdownImporter)),
+        "dayone" => Some(Box::new(dayone::DayOneImporter)),
+        "jrnl" => Some(Box::new(jrnl::JrnlImporter)),
+        _ => None,
+    }
+}
+
+// Parse a time like `2018-08-15T17:52:11+09:00`, `2018-08-15 17:52` or `2018-08-15`.
+// Times without a time zone are in the local time zone.
+pub fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Some(time.with_timezone(&Utc));
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%d %I:%M:%S %p", "%Y-%m-%d %I:%M %p"].iter()
+        .filter_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .next()
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)))?;
+
+    Local.from_local_datetime(&naive).earliest().map(|time| time.with_timezone(&Utc))
+}
+
+// Make pages with ids not used in the store.
+// Dated entries get `%Y-%m-%d` ids, followed by `-2`, `-3`, ... if the day is already used.
+pub fn to_pages(store: &dyn Store, entries: Vec<Entry>) -> Result<Vec<Page>, Error> {
+    let mut entries = entries;
+    entries.sort_by_key(|entry| entry.created);
+
+    let mut used = HashSet::new();
+    let mut pages = Vec::new();
+    for entry in entries {
+        let memo = entry.memo_id.is_some();
+        let base_id = match entry.memo_id {
+            Some(id) => id,
+            None => entry.created.with_timezone(&Local).format(DIARY_ID_FORMAT).to_string(),
+        };
+
+        let mut id = base_id.clone();
+        let mut n = 1;
+        while used.contains(&id) || store.exists(&id)? {
+            n += 1;
+            id = format!("{}-{}", base_id, n);
+        }
+        used.insert(id.clone());
+
+        pages.push(Page {
+            id,
+            header: PageHeader {
+                title: entry.title,
+                insert_title: true,
+                created: entry.created,
+                updated: Vec::new(),
+                memo,
+                tags: entry.tags,
+                prev: "NULL".to_string(),
+                next: "NULL".to_string(),
+            },
+            text: entry.text,
+        });
+    }
+
+    Ok(pages)
+}
+
+// Add the pages to the store and link all pages in order of created time.
+// Nothing is added if any id is invalid or already used.
+pub fn import(store: &mut dyn Store, pages: Vec<Page>) -> Result<usize, Error> {
+    let mut ids = HashSet::new();
+    let mut collisions = Vec::new();
+    for page in &pages {
+        is_valid_id(&page.id)?;
+        if !ids.insert(page.id.clone()) || store.exists(&page.id)? {
+            collisions.push(format!("`{}`", page.id));
+        }
+    }
+
+    if !collisions.is_empty() {
+        return Err(Error::AlreadyExists(format!("Already exists: {}", collisions.join(", "))));
+    }
+
+    let count = pages.len();
+    let mut all_pages = pages;
+    for id in store.ids()? {
+        all_pages.push(store.get(&id)?);
+    }
+
+    store.apply(relink(all_pages, |page| ids.contains(&page.id)))?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use store::MemoryStore;
+    use utils::new_page;
+
+    fn entry(title: &str, created: DateTime<Utc>, memo_id: Option<&str>) -> Entry {
+        Entry {
+            title: title.to_string(),
+            created,
+            text: String::new(),
+            tags: Vec::new(),
+            memo_id: memo_id.map(|id| id.to_string()),
+        }
+    }
+
+    #[test]
+    fn avoid_used_ids() {
+        let created = Local.with_ymd_and_hms(2018, 5, 1, 9, 0, 0).unwrap().with_timezone(&Utc);
+        let mut store = MemoryStore::new();
+        store.insert(new_page("2018-05-01")).unwrap();
+        store.insert(new_page("notes")).unwrap();
+
+        let entries = vec![
+            entry("Evening", created + Duration::hours(10), None),
+            entry("Morning", created, None),
+            entry("Next day", created + Duration::days(1), None),
+            entry("Notes", created, Some("notes")),
+        ];
+        let pages = to_pages(&store, entries).unwrap();
+        let ids: Vec<(&str, &str)> = pages.iter().map(|page| (page.id.as_str(), page.header.title.as_str())).collect();
+        assert_eq!(ids, vec![("2018-05-01-2", "Morning"), ("notes-2", "Notes"), ("2018-05-01-3", "Evening"), ("2018-05-02", "Next day")]);
+        assert!(pages[1].header.memo && !pages[0].header.memo);
+
+        assert_eq!(import(&mut store, pages).unwrap(), 4);
+        assert_eq!(store.ids().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn refuse_collisions() {
+        let mut store = MemoryStore::new();
+        store.insert(new_page("2018-05-01")).unwrap();
+
+        // Used in the store and duplicated in the pages
+        let pages = vec![new_page("2018-05-01"), new_page("2018-05-02"), new_page("2018-05-02"), new_page("2018-05-03")];
+        match import(&mut store, pages) {
+            Err(Error::AlreadyExists(message)) => assert_eq!(message, "Already exists: `2018-05-01`, `2018-05-02`"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        match import(&mut store, vec![new_page("2018-05-02"), new_page("../2018-05-03")]) {
+            Err(Error::InvalidId { .. }) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // Nothing is added
+        assert_eq!(store.ids().unwrap(), vec!["2018-05-01"]);
+        assert_eq!(store.head().unwrap(), "2018-05-01");
+    }
+}
@@ -0,0 +1,877 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::ops::Bound;
+use std::path::Path;
+
+use ansi_term::Colour::Red;
+use regex::{self, Regex, RegexBuilder};
+use serde_json;
+
+use crypto;
+use error::Error;
+use page::Page;
+use store::Store;
+use utils::{read_file, write_file};
+
+// File name of the full-text index in the app directory
+pub const INDEX_FILENAME: &str = "INDEX";
+// File name of pages changed since the index was saved, appended on each change
+pub const INDEX_LOG_FILENAME: &str = "INDEX_LOG";
+// Rebuild the index saved by another version
+const INDEX_VERSION: u32 = 1;
+
+// Parameters of BM25
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+// Matches in titles count as this many matches
+const TITLE_WEIGHT: f64 = 2.0;
+
+// Characters shown before the first match in snippets
+const SNIPPET_BEFORE: usize = 30;
+// Maximum characters of snippets
+const SNIPPET_LENGTH: usize = 100;
+
+#[derive(Debug, PartialEq)]
+pub struct Token {
+    pub text: String,
+    // Byte range in the source string
+    pub start: usize,
+    pub end: usize,
+}
+
+// Kana, CJK ideographs and Hangul, which are not separated by spaces
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0xFF66..=0xFF9F | 0xAC00..=0xD7AF)
+}
+
+// Split into lowercase words. Each CJK character is a word.
+pub fn tokenize(s: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in s.char_indices() {
+        if c.is_alphanumeric() && !is_cjk(c) || c == '_' {
+            if start.is_none() {
+                start = Some(i);
+            }
+            continue;
+        }
+
+        if let Some(start) = start.take() {
+            tokens.push(Token { text: s[start..i].to_lowercase(), start, end: i });
+        }
+
+        if is_cjk(c) {
+            let end = i + c.len_utf8();
+            tokens.push(Token { text: s[i..end].to_lowercase(), start: i, end });
+        }
+    }
+
+    if let Some(start) = start {
+        tokens.push(Token { text: s[start..].to_lowercase(), start, end: s.len() });
+    }
+
+    tokens
+}
+
+// Part of pages to search
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    All,
+    Title,
+    Body,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Term {
+    Word(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+    // Pages whose id contains the string
+    Id(String),
+}
+
+impl Term {
+    fn from_str(s: &str, prefix: bool) -> Option<Term> {
+        let mut words: Vec<String> = tokenize(s).into_iter().map(|token| token.text).collect();
+        match words.len() {
+            0 => None,
+            1 if prefix => Some(Term::Prefix(words.remove(0))),
+            1 => Some(Term::Word(words.remove(0))),
+            _ => Some(Term::Phrase(words)),
+        }
+    }
+
+    // Number of words matching from `tokens[i]`, if matched
+    fn match_at(&self, tokens: &[Token], i: usize) -> Option<usize> {
+        match self {
+            Term::Word(word) if tokens[i].text == *word => Some(1),
+            Term::Prefix(prefix) if tokens[i].text.starts_with(prefix.as_str()) => Some(1),
+            Term::Phrase(words) => {
+                let matched = tokens.len() - i >= words.len()
+                    && words.iter().zip(&tokens[i..]).all(|(word, token)| token.text == *word);
+                if matched { Some(words.len()) } else { None }
+            },
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Atom {
+    pub term: Term,
+    pub negated: bool,
+    // Field given by `title:` or `body:`
+    pub field: Option<Field>,
+}
+
+// Split into words at spaces outside quotes
+fn split_query(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in s.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c.is_whitespace() && !quoted {
+            if !word.is_empty() {
+                words.push(word);
+                word = String::new();
+            }
+            continue;
+        }
+        word.push(c);
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+// Groups joined by AND, each of which is atoms joined by OR
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    pub groups: Vec<Vec<Atom>>,
+}
+
+impl Query {
+    // Parse a query like `rust "error handling" OR result* -panic title:memo id:2018-`
+    pub fn parse(s: &str) -> Result<Query, Error> {
+        let mut groups: Vec<Vec<Atom>> = Vec::new();
+        let mut or = false;
+        let mut negated = false;
+
+        for word in split_query(s) {
+            match word.as_str() {
+                "OR" => { or = true; continue; },
+                "AND" => continue,
+                "NOT" => { negated = true; continue; },
+                _ => {},
+            }
+
+            let mut word = word.as_str();
+            if word.len() > 1 && word.starts_with('-') {
+                negated = true;
+                word = &word[1..];
+            }
+
+            let mut field = None;
+            let mut id = false;
+            if let Some(pos) = word.find(':') {
+                match &word[..pos] {
+                    "title" => field = Some(Field::Title),
+                    "body" => field = Some(Field::Body),
+                    "id" => id = true,
+                    _ => {},
+                }
+                if field.is_some() || id {
+                    word = &word[pos + 1..];
+                }
+            }
+
+            let quoted = word.starts_with('"');
+            let word = word.trim_matches('"');
+            let term = if id {
+                Some(Term::Id(word.to_string()))
+            } else {
+                Term::from_str(word, !quoted && word.ends_with('*'))
+            };
+
+            if let Some(term) = term {
+                let atom = Atom { term, negated, field };
+                match groups.last_mut() {
+                    Some(group) if or => group.push(atom),
+                    _ => groups.push(vec![atom]),
+                }
+            }
+
+            or = false;
+            negated = false;
+        }
+
+        if groups.is_empty() {
+            return Err(Error::Parse(String::from("The query has no words")));
+        }
+
+        Ok(Query { groups })
+    }
+
+    // Byte ranges of matches in the text
+    pub fn highlights(&self, text: &str) -> Vec<(usize, usize)> {
+        let terms: Vec<&Term> = self.groups.iter().flatten()
+            .filter(|atom| !atom.negated && atom.field != Some(Field::Title))
+            .map(|atom| &atom.term)
+            .collect();
+        let tokens = tokenize(text);
+
+        let mut ranges = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            match terms.iter().filter_map(|term| term.match_at(&tokens, i)).max() {
+                Some(len) => {
+                    ranges.push((tokens[i].start, tokens[i + len - 1].end));
+                    i += len;
+                },
+                None => i += 1,
+            }
+        }
+
+        ranges
+    }
+
+}
+
+// `text[start..end]` with the ranges highlighted
+pub fn paint_ranges(text: &str, start: usize, end: usize, ranges: &[(usize, usize)]) -> String {
+    let mut painted = String::new();
+    let mut pos = start;
+    for &(range_start, range_end) in ranges {
+        if range_end <= start || range_start >= end {
+            continue;
+        }
+        let range_start = range_start.max(pos);
+        let range_end = range_end.min(end);
+        painted.push_str(&text[pos..range_start]);
+        painted.push_str(&Red.bold().paint(&text[range_start..range_end]).to_string());
+        pos = range_end;
+    }
+    painted.push_str(&text[pos..end]);
+
+    painted
+}
+
+// A part of the text around the first range with the ranges highlighted
+pub fn snippet(text: &str, ranges: &[(usize, usize)]) -> Option<String> {
+    let first = ranges.first()?.0;
+
+    // Byte range of the snippet at character boundaries
+    let start = text[..first].char_indices().rev().nth(SNIPPET_BEFORE - 1).map(|(i, _)| i).unwrap_or(0);
+    let end = text[start..].char_indices().nth(SNIPPET_LENGTH).map(|(i, _)| start + i).unwrap_or(text.len());
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push_str("...");
+    }
+    snippet.push_str(&paint_ranges(text, start, end, ranges));
+    if end < text.len() {
+        snippet.push_str("...");
+    }
+
+    Some(snippet.replace(['\n', '\r'], " "))
+}
+
+// Pattern of `diary search --regex`, `--ignore-case` or `--word` matched against each page
+// instead of the index. A `title:`, `body:` or `id:` prefix chooses the part to match.
+pub struct Pattern {
+    re: Regex,
+    // `None` matches ids
+    pub field: Option<Field>,
+}
+
+impl Pattern {
+    pub fn parse(query: &str, is_regex: bool, ignore_case: bool, word: bool) -> Result<Pattern, Error> {
+        let (field, pattern) = match query.find(':').map(|pos| (&query[..pos], &query[pos + 1..])) {
+            Some(("title", pattern)) => (Some(Field::Title), pattern),
+            Some(("body", pattern)) => (Some(Field::Body), pattern),
+            Some(("id", pattern)) => (None, pattern),
+            _ => (Some(Field::All), query),
+        };
+
+        let mut pattern = if is_regex { pattern.to_string() } else { regex::escape(pattern) };
+        if word {
+            pattern = format!(r"\b(?:{})\b", pattern);
+        }
+        let re = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|err| Error::Parse(format!("Invalid regex: {}", err)))?;
+
+        Ok(Pattern { re, field })
+    }
+
+    // Byte ranges of matches in the text, or `None` if the page does not match
+    pub fn matches(&self, page: &Page) -> Option<Vec<(usize, usize)>> {
+        let ranges: Vec<(usize, usize)> = match self.field {
+            Some(Field::Title) => Vec::new(),
+            _ => self.re.find_iter(&page.text).map(|m| (m.start(), m.end())).collect(),
+        };
+        let matched = match self.field {
+            Some(Field::All) => !ranges.is_empty() || self.re.is_match(&page.header.title),
+            Some(Field::Title) => self.re.is_match(&page.header.title),
+            Some(Field::Body) => !ranges.is_empty(),
+            None => self.re.is_match(&page.id),
+        };
+
+        if matched { Some(ranges) } else { None }
+    }
+}
+
+// Line printed by `diary search --context`
+#[derive(Debug, PartialEq)]
+pub enum ContextLine {
+    // Line number starting at 1 and the byte range of the line
+    Line { number: usize, start: usize, end: usize, matched: bool },
+    // Between lines which are not next to each other
+    Separator,
+}
+
+// Lines with the ranges and `context` lines around them
+pub fn context_lines(text: &str, ranges: &[(usize, usize)], context: usize) -> Vec<ContextLine> {
+    // Byte ranges of lines
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for line in text.split('\n') {
+        lines.push((start, start + line.len()));
+        start += line.len() + 1;
+    }
+
+    let matched: Vec<bool> = lines.iter()
+        .map(|&(start, end)| ranges.iter().any(|&(range_start, range_end)| range_start < end && range_end > start))
+        .collect();
+
+    let mut result = Vec::new();
+    let mut last_printed = None;
+    for (i, &(start, end)) in lines.iter().enumerate() {
+        let near = (i.saturating_sub(context)..(i + context + 1).min(lines.len())).any(|j| matched[j]);
+        if !near {
+            continue;
+        }
+
+        if let Some(last) = last_printed {
+            if last + 1 != i {
+                result.push(ContextLine::Separator);
+            }
+        }
+
+        result.push(ContextLine::Line { number: i + 1, start, end, matched: matched[i] });
+        last_printed = Some(i);
+    }
+
+    result
+}
+
+// A line of the index log setting the words of the page, or removing the page if `page` is `None`.
+// Replaying lines already in the index gives the same index.
+#[derive(Debug, Serialize, Deserialize)]
+struct LogEntry {
+    id: String,
+    page: Option<(String, String)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Document {
+    // Number of words in the title. Words of the text are at positions after `title_len + 1`.
+    title_len: usize,
+    len: usize,
+}
+
+// Inverted index from words to positions in pages
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Index {
+    version: u32,
+    postings: BTreeMap<String, BTreeMap<String, Vec<usize>>>,
+    documents: BTreeMap<String, Document>,
+}
+
+impl Index {
+    pub fn new() -> Index {
+        Index {
+            version: INDEX_VERSION,
+            postings: BTreeMap::new(),
+            documents: BTreeMap::new(),
+        }
+    }
+
+    pub fn build(store: &dyn Store) -> Result<Index, Error> {
+        let mut index = Index::new();
+        for id in store.ids()? {
+            index.add(&store.get(&id)?);
+        }
+
+        Ok(index)
+    }
+
+    // The saved index with changes in the log applied, if it exists and is up to date.
+    // Also returns whether the log is large enough to be merged into the index.
+    // The index of an encrypted diary is never saved, as it contains words of pages.
+    fn read_saved(directory: &str) -> Result<Option<(Index, bool)>, Error> {
+        let path = Path::new(directory).join(INDEX_FILENAME);
+        if crypto::is_enabled(directory) || !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = read_file(&path)
+            .map_err(|err| Error::io(format!("Unable to read index `{}`", path.to_string_lossy()), err))?;
+
+        let mut index = match serde_json::from_str::<Index>(&contents) {
+            Ok(index) => index,
+            // Rebuild the broken index
+            Err(_) => return Ok(None),
+        };
+        if index.version != INDEX_VERSION || !index.is_consistent() {
+            return Ok(None);
+        }
+
+        let log_path = Path::new(directory).join(INDEX_LOG_FILENAME);
+        let log = if log_path.exists() {
+            read_file(&log_path)
+                .map_err(|err| Error::io(format!("Unable to read index log `{}`", log_path.to_string_lossy()), err))?
+        } else {
+            String::new()
+        };
+
+        for line in log.lines() {
+            match serde_json::from_str::<LogEntry>(line) {
+                Ok(LogEntry { id, page: Some((title, text)) }) => index.add_words(&id, &title, &text),
+                Ok(LogEntry { id, page: None }) => index.remove(&id),
+                // A line being written by another process
+                Err(_) => return Ok(None),
+            }
+        }
+
+        Ok(Some((index, log.len() > contents.len() / 2)))
+    }
+
+    // Load the index, building and saving it if it does not exist or is outdated
+    pub fn open(directory: &str, store: &dyn Store) -> Result<Index, Error> {
+        match Index::read_saved(directory)? {
+            Some((index, false)) => return Ok(index),
+            Some((index, true)) => {
+                index.save(directory)?;
+                return Ok(index);
+            },
+            None => {},
+        }
+
+        let index = Index::build(store)?;
+        index.save(directory)?;
+
+        Ok(index)
+    }
+
+    // Load the index, building it in memory without saving if it does not exist or is outdated.
+    // Used without the lock, not to race a process writing the index.
+    pub fn load(directory: &str, store: &dyn Store) -> Result<Index, Error> {
+        match Index::read_saved(directory)? {
+            Some((index, _)) => Ok(index),
+            None => Index::build(store),
+        }
+    }
+
+    // Save the whole index, merging the log into it
+    pub fn save(&self, directory: &str) -> Result<(), Error> {
+        if crypto::is_enabled(directory) {
+            return Ok(());
+        }
+
+        let path = Path::new(directory).join(INDEX_FILENAME);
+        let contents = serde_json::to_string(self).unwrap();
+        write_file(&path, &contents)
+            .map_err(|err| Error::io(format!("Unable to write index `{}`", path.to_string_lossy()), err))?;
+
+        // Lines left after a crash here are already in the index
+        let log_path = Path::new(directory).join(INDEX_LOG_FILENAME);
+        if log_path.exists() {
+            fs::remove_file(&log_path)
+                .map_err(|err| Error::io(format!("Unable to remove index log `{}`", log_path.to_string_lossy()), err))?;
+        }
+
+        Ok(())
+    }
+
+    // Check that every page in postings has its document
+    fn is_consistent(&self) -> bool {
+        self.postings.values().flat_map(|documents| documents.keys()).all(|id| self.documents.contains_key(id))
+    }
+
+    // Add or replace the page
+    pub fn add(&mut self, page: &Page) {
+        self.add_words(&page.id, &page.header.title, &page.text);
+    }
+
+    fn add_words(&mut self, id: &str, title: &str, text: &str) {
+        self.remove(id);
+
+        let title = tokenize(title);
+        let text = tokenize(text);
+        let title_len = title.len();
+        let len = title_len + text.len();
+
+        let positions = title.into_iter().enumerate()
+            .chain(text.into_iter().enumerate().map(|(i, token)| (title_len + 1 + i, token)));
+        for (position, token) in positions {
+            self.postings.entry(token.text).or_default()
+                .entry(id.to_string()).or_default()
+                .push(position);
+        }
+
+        self.documents.insert(id.to_string(), Document { title_len, len });
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if self.documents.remove(id).is_none() {
+            return;
+        }
+
+        self.postings.retain(|_, documents| {
+            documents.remove(id);
+            !documents.is_empty()
+        });
+    }
+
+    pub fn rename(&mut self, prev_id: &str, next_id: &str) {
+        if let Some(document) = self.documents.remove(prev_id) {
+            self.documents.insert(next_id.to_string(), document);
+        }
+
+        for documents in self.postings.values_mut() {
+            if let Some(positions) = documents.remove(prev_id) {
+                documents.insert(next_id.to_string(), positions);
+            }
+        }
+    }
+
+    fn in_field(&self, id: &str, position: usize, field: Field) -> bool {
+        let title_len = match self.documents.get(id) {
+            Some(document) => document.title_len,
+            None => return false,
+        };
+        match field {
+            Field::All => true,
+            Field::Title => position < title_len,
+            Field::Body => position > title_len,
+        }
+    }
+
+    // Positions of the word in each page
+    fn positions(&self, word: &str, field: Field) -> HashMap<&str, HashSet<usize>> {
+        let mut result = HashMap::new();
+        if let Some(documents) = self.postings.get(word) {
+            for (id, positions) in documents {
+                let positions: HashSet<usize> = positions.iter().cloned().filter(|&position| self.in_field(id, position, field)).collect();
+                if !positions.is_empty() {
+                    result.insert(id.as_str(), positions);
+                }
+            }
+        }
+
+        result
+    }
+
+    // Weight of a match at the position
+    fn weight(&self, id: &str, position: usize) -> f64 {
+        match self.documents.get(id) {
+            Some(document) if position < document.title_len => TITLE_WEIGHT,
+            _ => 1.0,
+        }
+    }
+
+    // Weighted number of matches in each page
+    fn matches(&self, term: &Term, field: Field) -> HashMap<&str, f64> {
+        let mut result = HashMap::new();
+        match term {
+            // Match without affecting the score
+            Term::Id(pattern) => {
+                for id in self.documents.keys().filter(|id| id.contains(pattern.as_str())) {
+                    result.insert(id.as_str(), 0.0);
+                }
+            },
+            Term::Word(word) => {
+                for (id, positions) in self.positions(word, field) {
+                    result.insert(id, positions.iter().map(|&position| self.weight(id, position)).sum());
+                }
+            },
+            Term::Prefix(prefix) => {
+                let words = self.postings.range::<str, _>((Bound::Included(prefix.as_str()), Bound::Unbounded))
+                    .map(|(word, _)| word)
+                    .take_while(|word| word.starts_with(prefix.as_str()));
+                for word in words {
+                    for (id, positions) in self.positions(word, field) {
+                        *result.entry(id).or_insert(0.0) += positions.iter().map(|&position| self.weight(id, position)).sum::<f64>();
+                    }
+                }
+            },
+            Term::Phrase(words) => {
+                let positions: Vec<_> = words.iter().map(|word| self.positions(word, field)).collect();
+
+                for (&id, starts) in &positions[0] {
+                    let tf: f64 = starts.iter()
+                        .filter(|&&start| positions[1..].iter().enumerate().all(|(i, word_positions)| {
+                            word_positions.get(id).map(|p| p.contains(&(start + i + 1))).unwrap_or(false)
+                        }))
+                        .map(|&start| self.weight(id, start))
+                        .sum();
+                    if tf > 0.0 {
+                        result.insert(id, tf);
+                    }
+                }
+            },
+        }
+
+        result
+    }
+
+    // Ids of matched pages with scores, in descending order of the score
+    pub fn search(&self, query: &Query, field: Field) -> Vec<(String, f64)> {
+        let count = self.documents.len() as f64;
+        let average_len = self.documents.values().map(|document| document.len).sum::<usize>() as f64 / count.max(1.0);
+        // All documents are empty
+        let average_len = if average_len == 0.0 { 1.0 } else { average_len };
+
+        let mut candidates: Option<HashSet<&str>> = None;
+        let mut scores: HashMap<&str, f64> = HashMap::new();
+
+        for group in &query.groups {
+            let mut matched: HashSet<&str> = HashSet::new();
+            for atom in group {
+                let matches = self.matches(&atom.term, atom.field.unwrap_or(field));
+
+                if atom.negated {
+                    matched.extend(self.documents.keys().map(|id| id.as_str()).filter(|id| !matches.contains_key(id)));
+                    continue;
+                }
+
+                let idf = (1.0 + (count - matches.len() as f64 + 0.5) / (matches.len() as f64 + 0.5)).ln();
+                for (&id, &tf) in &matches {
+                    let len = self.documents.get(id).map(|document| document.len).unwrap_or(0) as f64;
+                    *scores.entry(id).or_insert(0.0) += idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / average_len));
+                    matched.insert(id);
+                }
+            }
+
+            candidates = Some(match candidates {
+                Some(candidates) => candidates.intersection(&matched).cloned().collect(),
+                None => matched,
+            });
+        }
+
+        let mut results: Vec<(String, f64)> = candidates.unwrap_or_default().into_iter()
+            .map(|id| (id.to_string(), scores.get(id).cloned().unwrap_or(0.0)))
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+
+        results
+    }
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index::new()
+    }
+}
+
+// Append the change to the log of the saved index, instead of rewriting the whole index
+fn append_log(directory: &str, entry: &LogEntry) -> Result<(), Error> {
+    // The index is built with all pages when it is opened next time
+    if crypto::is_enabled(directory) || !Path::new(directory).join(INDEX_FILENAME).exists() {
+        return Ok(());
+    }
+
+    let path = Path::new(directory).join(INDEX_LOG_FILENAME);
+    OpenOptions::new().create(true).append(true).open(&path)
+        .and_then(|mut file| file.write_all(format!("{}\n", serde_json::to_string(entry).unwrap()).as_bytes()))
+        .map_err(|err| Error::io(format!("Unable to write index log `{}`", path.to_string_lossy()), err))
+}
+
+// Add or replace the page in the saved index
+pub fn update_page(directory: &str, page: &Page) -> Result<(), Error> {
+    append_log(directory, &LogEntry { id: page.id.clone(), page: Some((page.header.title.clone(), page.text.clone())) })
+}
+
+pub fn remove_page(directory: &str, id: &str) -> Result<(), Error> {
+    append_log(directory, &LogEntry { id: id.to_string(), page: None })
+}
+
+// Called after the page is renamed in the store
+pub fn rename_page(directory: &str, store: &dyn Store, prev_id: &str, next_id: &str) -> Result<(), Error> {
+    remove_page(directory, prev_id)?;
+    update_page(directory, &store.get(next_id)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use store::MemoryStore;
+    use utils::new_page;
+
+    fn search(index: &Index, query: &str, field: Field) -> Vec<String> {
+        index.search(&Query::parse(query).unwrap(), field).into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn search_ranked() {
+        let mut index = Index::new();
+        for &(id, title, text) in &[
+            ("a", "Rust", "error handling with result types"),
+            ("b", "Cooking", "rust on the pan. error"),
+            ("c", "日記", "今日はRustを書いた"),
+        ] {
+            let mut page = new_page(id);
+            page.header.title = title.to_string();
+            page.text = text.to_string();
+            index.add(&page);
+        }
+
+        assert_eq!(search(&index, "rust", Field::All), vec!["a", "b", "c"]);
+        assert_eq!(search(&index, "rust -pan", Field::All), vec!["a", "c"]);
+        assert_eq!(search(&index, "\"error handling\"", Field::All), vec!["a"]);
+        assert_eq!(search(&index, "handl* OR cooking", Field::All).len(), 2);
+        assert_eq!(search(&index, "rust NOT rust", Field::All).len(), 0);
+        assert_eq!(search(&index, "今日", Field::All), vec!["c"]);
+        let mut body = search(&index, "rust", Field::Body);
+        body.sort();
+        assert_eq!(body, vec!["b", "c"]);
+        assert_eq!(search(&index, "rust", Field::Title), vec!["a"]);
+        assert_eq!(search(&index, "title:rust", Field::All), vec!["a"]);
+        assert_eq!(search(&index, "body:\"rust on\" id:b", Field::All), vec!["b"]);
+
+        index.rename("b", "bb");
+        index.remove("a");
+        assert_eq!(search(&index, "error", Field::All), vec!["bb"]);
+
+        // Scores of empty pages
+        let mut empty = Index::new();
+        for id in &["x", "y"] {
+            let mut page = new_page(id);
+            page.header.title = String::new();
+            empty.add(&page);
+        }
+        assert_eq!(search(&empty, "id:x OR id:y", Field::All), vec!["y", "x"]);
+
+        let snippet = snippet("error handling", &Query::parse("handling").unwrap().highlights("error handling")).unwrap();
+        assert_eq!(snippet, format!("error {}", Red.bold().paint("handling")));
+    }
+
+    #[test]
+    fn match_pattern() {
+        let mut page = new_page("2018-05-01");
+        page.header.title = "Rust meetup".to_string();
+        page.text = "Error handling in rust.\nRusty tools".to_string();
+
+        let ranges = |query: &str, is_regex: bool, ignore_case: bool, word: bool| {
+            Pattern::parse(query, is_regex, ignore_case, word).unwrap().matches(&page)
+        };
+
+        assert_eq!(ranges("rust", false, false, false), Some(vec![(18, 22)]));
+        assert_eq!(ranges("rust", false, true, false), Some(vec![(18, 22), (24, 28)]));
+        assert_eq!(ranges("rust", false, true, true), Some(vec![(18, 22)]));
+        assert_eq!(ranges(r"r\w+y", true, true, false), Some(vec![(24, 29)]));
+        assert_eq!(ranges(r"r\w+y", false, true, false), None);
+        assert_eq!(ranges("r.st.", false, false, false), None);
+        assert_eq!(ranges("r.st.", true, false, false), Some(vec![(18, 23)]));
+
+        // Parts of the page
+        assert_eq!(ranges("Meetup", false, true, false), Some(Vec::new()));
+        assert_eq!(ranges("body:meetup", false, true, false), None);
+        assert_eq!(ranges("title:rust", false, true, false), Some(Vec::new()));
+        assert_eq!(ranges("title:tools", false, true, false), None);
+        assert_eq!(ranges(r"id:^2018-\d{2}", true, false, false), Some(Vec::new()));
+        assert_eq!(ranges("id:2019", true, false, false), None);
+        assert_eq!(ranges("in rust.", false, false, false), Some(vec![(15, 23)]));
+
+        match Pattern::parse("(", true, false, false) {
+            Err(Error::Parse(_)) => (),
+            _ => panic!("invalid regex is parsed"),
+        }
+    }
+
+    #[test]
+    fn lines_around_matches() {
+        let text = "a\nb match\nc\nd\ne\nf match\ng";
+        let mut page = new_page("2018-05-01");
+        page.text = text.to_string();
+
+        let ranges = Pattern::parse("match", false, false, false).unwrap().matches(&page).unwrap();
+        let numbers = |context: usize| -> Vec<String> {
+            context_lines(text, &ranges, context).into_iter().map(|line| match line {
+                ContextLine::Line { number, start, end, matched } => {
+                    format!("{}{}{}", number, if matched { ":" } else { "-" }, &text[start..end])
+                },
+                ContextLine::Separator => "--".to_string(),
+            }).collect()
+        };
+
+        assert_eq!(numbers(0), vec!["2:b match", "--", "6:f match"]);
+        assert_eq!(numbers(1), vec!["1-a", "2:b match", "3-c", "--", "5-e", "6:f match", "7-g"]);
+        assert_eq!(numbers(2), vec!["1-a", "2:b match", "3-c", "4-d", "5-e", "6:f match", "7-g"]);
+        assert!(context_lines(text, &[], 1).is_empty());
+
+        // A match over lines
+        let ranges = Pattern::parse(r"c\nd", true, false, false).unwrap().matches(&page).unwrap();
+        assert_eq!(context_lines(text, &ranges, 0), vec![
+            ContextLine::Line { number: 3, start: 10, end: 11, matched: true },
+            ContextLine::Line { number: 4, start: 12, end: 13, matched: true },
+        ]);
+    }
+
+    #[test]
+    fn update_saved_index() {
+        let directory = env::temp_dir().join(format!("diary-index-test-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let dir_str = directory.to_str().unwrap();
+
+        let mut store = MemoryStore::new();
+        for &(id, text) in &[("a", "apple"), ("b", "banana")] {
+            let mut page = new_page(id);
+            page.text = text.to_string();
+            store.insert(page).unwrap();
+        }
+        Index::open(dir_str, &store).unwrap();
+        let saved = read_file(&directory.join(INDEX_FILENAME)).unwrap();
+
+        // Changes are appended to the log without rewriting the index
+        let mut page = store.get("a").unwrap();
+        page.text = "cherry".to_string();
+        store.put(&page).unwrap();
+        update_page(dir_str, &page).unwrap();
+        store.rename("b", "bb").unwrap();
+        rename_page(dir_str, &store, "b", "bb").unwrap();
+        assert_eq!(read_file(&directory.join(INDEX_FILENAME)).unwrap(), saved);
+
+        let index = Index::load(dir_str, &MemoryStore::new()).unwrap();
+        assert_eq!(search(&index, "cherry", Field::All), vec!["a"]);
+        assert!(search(&index, "apple", Field::All).is_empty());
+        assert_eq!(search(&index, "banana", Field::All), vec!["bb"]);
+
+        // A line half-written by a crash, or an index with words of unknown pages is built again
+        fs::write(directory.join(INDEX_LOG_FILENAME), "{\"id\":").unwrap();
+        assert_eq!(search(&Index::open(dir_str, &store).unwrap(), "cherry", Field::All), vec!["a"]);
+        assert!(!directory.join(INDEX_LOG_FILENAME).exists());
+
+        let mut broken = Index::new();
+        broken.add(&new_page("x"));
+        broken.documents.clear();
+        broken.save(dir_str).unwrap();
+        let index = Index::open(dir_str, &store).unwrap();
+        assert!(index.is_consistent());
+        assert!(search(&index, "x", Field::All).is_empty());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use crypto::ENCRYPTION_FILENAME;
+use error::Error;
+use index::INDEX_FILENAME;
+use utils::*;
+
+// File name to save operations of an unfinished multi-file update
+pub const JOURNAL_FILENAME: &str = "JOURNAL";
+// Extension of files being written by `write_file`
+pub const TEMPORARY_EXTENSION: &str = "tmp";
+
+// A file operation relative to the app directory
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Operation {
+    Write { path: PathBuf, contents: String },
+    Rename { from: PathBuf, to: PathBuf },
+    Remove { path: PathBuf },
+}
+
+// Operations applied all together. The operations are saved to the journal file before
+// they are applied, so that an interrupted update can be rolled forward on the next start.
+pub struct Journal {
+    directory: PathBuf,
+    operations: Vec<Operation>,
+}
+
+impl Journal {
+    pub fn new(directory: &str) -> Journal {
+        Journal {
+            directory: PathBuf::from(directory),
+            operations: Vec::new(),
+        }
+    }
+
+    pub fn write(&mut self, path: PathBuf, contents: String) {
+        self.operations.push(Operation::Write { path, contents });
+    }
+
+    pub fn write_head(&mut self, id: &str) {
+        self.write(PathBuf::from(HEAD_FILENAME), id.to_string());
+    }
+
+    pub fn rename(&mut self, from: PathBuf, to: PathBuf) {
+        self.operations.push(Operation::Rename { from, to });
+    }
+
+    pub fn remove(&mut self, path: PathBuf) {
+        self.operations.push(Operation::Remove { path });
+    }
+
+    pub fn commit(self) -> Result<(), Error> {
+        let journal_path = self.directory.join(JOURNAL_FILENAME);
+
+        let journal_str = serde_json::to_string(&self.operations)
+            .map_err(|err| Error::Parse(format!("Unable to serialize journal: {}", err)))?;
+        write_file(&journal_path, &journal_str)
+            .map_err(|err| Error::io(format!("Unable to write journal `{}`", journal_path.to_string_lossy()), err))?;
+
+        apply(&self.directory, &self.operations)?;
+
+        fs::remove_file(&journal_path)
+            .map_err(|err| Error::io(format!("Unable to remove journal `{}`", journal_path.to_string_lossy()), err))?;
+
+        Ok(())
+    }
+}
+
+fn create_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) if !parent.exists() => fs::create_dir_all(parent)
+            .map_err(|err| Error::io(format!("Unable to create directory `{}`", parent.to_string_lossy()), err)),
+        _ => Ok(()),
+    }
+}
+
+// Apply operations. Every operation can be applied again after it has been applied.
+fn apply(directory: &Path, operations: &[Operation]) -> Result<(), Error> {
+    for operation in operations {
+        match operation {
+            Operation::Write { path, contents } => {
+                let path = directory.join(path);
+                create_parent(&path)?;
+                write_file(&path, contents)
+                    .map_err(|err| Error::io(format!("Unable to write to file `{}`", path.to_string_lossy()), err))?;
+            },
+            Operation::Rename { from, to } => {
+                let from = directory.join(from);
+                let to = directory.join(to);
+                if from.exists() {
+                    create_parent(&to)?;
+                    fs::rename(&from, &to)
+                        .map_err(|err| Error::io(format!("Unable to rename file from `{}` to `{}`", from.to_string_lossy(), to.to_string_lossy()), err))?;
+                }
+            },
+            Operation::Remove { path } => {
+                let path = directory.join(path);
+                if path.exists() {
+                    fs::remove_file(&path)
+                        .map_err(|err| Error::io(format!("Unable to remove file `{}`", path.to_string_lossy()), err))?;
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+// Remove the file if it is half-written by `write_file`
+fn remove_temporary_file(path: &Path) -> Result<(), Error> {
+    if !path.is_file() {
+        return Ok(());
+    }
+
+    fs::remove_file(path)
+        .map_err(|err| Error::io(format!("Unable to remove file `{}`", path.to_string_lossy()), err))
+}
+
+// Remove files half-written by `write_file`, which are the file names with `.tmp` appended.
+// Only files written by updates are removed, not recovery files or temporary files to edit whose
+// page ids may end with `.tmp`.
+fn remove_temporary_files(directory: &Path) -> Result<(), Error> {
+    for name in &[HEAD_FILENAME, JOURNAL_FILENAME, INDEX_FILENAME, ENCRYPTION_FILENAME, "config.toml", ".gitignore"] {
+        remove_temporary_file(&directory.join(format!("{}.{}", name, TEMPORARY_EXTENSION)))?;
+    }
+
+    // Pages in `pages/` and `trash/`, and revisions in `revisions/<id>/` and `trash/revisions/<name>/`
+    let mut dirs = vec![directory.join(PAGES_DIR), directory.join(TRASH_DIR)];
+    for revisions_dir in &[directory.join(REVISIONS_DIR), directory.join(TRASH_DIR).join(REVISIONS_DIR)] {
+        if let Ok(entries) = fs::read_dir(revisions_dir) {
+            dirs.extend(entries.flatten().map(|entry| entry.path()).filter(|path| path.is_dir()));
+        }
+    }
+
+    let suffix = format!(".{}.{}", PAGE_EXTENSION, TEMPORARY_EXTENSION);
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().ends_with(&suffix) {
+                remove_temporary_file(&entry.path())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Finish an update interrupted by a crash. If the journal was saved, its operations are
+// applied again, otherwise nothing has been changed and the half-written files are removed.
+// Returns true if the journal was rolled forward.
+pub fn recover(directory: &str) -> Result<bool, Error> {
+    let directory = Path::new(directory);
+    remove_temporary_files(directory)?;
+
+    let journal_path = directory.join(JOURNAL_FILENAME);
+    if !journal_path.exists() {
+        return Ok(false);
+    }
+
+    let contents = read_file(&journal_path)
+        .map_err(|err| Error::io(format!("Unable to read journal `{}`", journal_path.to_string_lossy()), err))?;
+    let operations: Vec<Operation> = serde_json::from_str(&contents)
+        .map_err(|err| Error::Parse(format!("Unable to parse journal `{}`: {}", journal_path.to_string_lossy(), err)))?;
+
+    apply(directory, &operations)?;
+
+    fs::remove_file(&journal_path)
+        .map_err(|err| Error::io(format!("Unable to remove journal `{}`", journal_path.to_string_lossy()), err))?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn page_path(id: &str) -> PathBuf {
+        Path::new(PAGES_DIR).join(format!("{}.{}", id, PAGE_EXTENSION))
+    }
+
+    #[test]
+    fn recover_rolls_forward() {
+        let directory = env::temp_dir().join(format!("diary-journal-test-{}", process::id()));
+        fs::create_dir_all(directory.join(PAGES_DIR)).unwrap();
+        write_file(&directory.join(page_path("old")), "page").unwrap();
+        write_file(&directory.join(HEAD_FILENAME), "old").unwrap();
+        write_file(&directory.join(PAGES_DIR).join("half.page.tmp"), "half").unwrap();
+        fs::create_dir_all(directory.join(REVISIONS_DIR).join("old")).unwrap();
+        write_file(&directory.join(REVISIONS_DIR).join("old").join("1.page.tmp"), "half").unwrap();
+        write_file(&directory.join("INDEX.tmp"), "half").unwrap();
+        // Files of a page whose id ends with `.tmp`
+        fs::create_dir_all(directory.join(RECOVERY_DIR)).unwrap();
+        write_file(&directory.join(RECOVERY_DIR).join("20180501000000.notes.tmp"), "recovery").unwrap();
+        write_file(&directory.join(format!("{}.1.notes.tmp", TEMPORARY_FILE_TO_EDIT)), "edit").unwrap();
+
+        // Journal saved but not applied
+        let operations = vec![
+            Operation::Rename { from: page_path("old"), to: page_path("new") },
+            Operation::Write { path: PathBuf::from(HEAD_FILENAME), contents: "new".to_string() },
+        ];
+        write_file(&directory.join(JOURNAL_FILENAME), &serde_json::to_string(&operations).unwrap()).unwrap();
+
+        let dir_str = directory.to_str().unwrap();
+        assert!(recover(dir_str).unwrap());
+        assert!(directory.join(page_path("new")).exists());
+        assert!(!directory.join(page_path("old")).exists());
+        assert!(!directory.join(PAGES_DIR).join("half.page.tmp").exists());
+        assert!(!directory.join(REVISIONS_DIR).join("old").join("1.page.tmp").exists());
+        assert!(!directory.join("INDEX.tmp").exists());
+        assert!(directory.join(RECOVERY_DIR).join("20180501000000.notes.tmp").exists());
+        assert!(directory.join(format!("{}.1.notes.tmp", TEMPORARY_FILE_TO_EDIT)).exists());
+        assert_eq!(read_file(&directory.join(HEAD_FILENAME)).unwrap(), "new");
+        assert!(!recover(dir_str).unwrap());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use serde_json;
+
+use error::Error;
+use page::Page;
+use store::Store;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // An array of pages
+    Json,
+    // A page per line
+    Jsonl,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format, Error> {
+        match name {
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => Err(Error::Parse(format!("Unknown format `{}`. use json or jsonl", name))),
+        }
+    }
+}
+
+// All pages in order of created time
+pub fn load_all(store: &dyn Store) -> Result<Vec<Page>, Error> {
+    let mut pages = Vec::new();
+    for id in store.ids()? {
+        pages.push(store.get(&id)?);
+    }
+    pages.sort_by(|a, b| a.header.created.cmp(&b.header.created).then_with(|| a.id.cmp(&b.id)));
+
+    Ok(pages)
+}
+
+pub fn to_string(pages: &[Page], format: Format) -> String {
+    match format {
+        Format::Json => serde_json::to_string_pretty(pages).unwrap(),
+        Format::Jsonl => pages.iter()
+            .map(|page| format!("{}\n", serde_json::to_string(page).unwrap()))
+            .collect(),
+    }
+}
+
+// Parse pages. The format is detected from the contents if it is not given.
+pub fn from_str(s: &str, format: Option<Format>) -> Result<Vec<Page>, Error> {
+    let format = format.unwrap_or(if s.trim_start().starts_with('[') { Format::Json } else { Format::Jsonl });
+
+    match format {
+        Format::Json => serde_json::from_str(s)
+            .map_err(|err| Error::Parse(format!("Invalid JSON: {}", err))),
+        Format::Jsonl => s.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| serde_json::from_str(line)
+                 .map_err(|err| Error::Parse(format!("Invalid JSON at line {}: {}", i + 1, err))))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use import::import;
+    use store::MemoryStore;
+    use utils::new_page;
+
+    fn store_with_pages() -> MemoryStore {
+        let mut store = MemoryStore::new();
+        let created = Utc::now() - Duration::days(10);
+        for (i, &(id, title, text, memo)) in [
+            ("2018-05-01", "First", "line \"quoted\"\n\nunicode 日記\n", false),
+            ("ideas", "Ideas", "- #rust\n- tabs\there", true),
+            ("2018-05-03", "", "", false),
+        ].iter().enumerate() {
+            let mut page = new_page(id);
+            page.header.title = title.to_string();
+            page.header.created = created + Duration::hours(i as i64);
+            page.header.updated = vec![created + Duration::minutes(i as i64 * 90)];
+            page.header.memo = memo;
+            page.header.tags = vec!["work".to_string()];
+            page.text = text.to_string();
+            store.insert(page).unwrap();
+        }
+
+        store
+    }
+
+    #[test]
+    fn export_and_import() {
+        let store = store_with_pages();
+        let pages = load_all(&store).unwrap();
+        let ids: Vec<&str> = pages.iter().map(|page| page.id.as_str()).collect();
+        assert_eq!(ids, vec!["2018-05-01", "ideas", "2018-05-03"]);
+
+        for &format in &[Format::Json, Format::Jsonl] {
+            let exported = to_string(&pages, format);
+            let parsed = from_str(&exported, None).unwrap();
+            assert_eq!(to_string(&parsed, format), exported);
+            assert_eq!(to_string(&from_str(&exported, Some(format)).unwrap(), format), exported);
+
+            // Same pages and chain in another diary
+            let mut imported = MemoryStore::new();
+            assert_eq!(import(&mut imported, parsed).unwrap(), 3);
+            assert_eq!(to_string(&load_all(&imported).unwrap(), format), exported);
+            assert_eq!(imported.head().unwrap(), "2018-05-03");
+            assert_eq!(imported.get("ideas").unwrap().header.prev, "2018-05-01");
+        }
+
+        assert_eq!(Format::from_name("jsonl").unwrap(), Format::Jsonl);
+        assert!(Format::from_name("xml").is_err());
+    }
+
+    #[test]
+    fn invalid_json() {
+        let jsonl = to_string(&load_all(&store_with_pages()).unwrap(), Format::Jsonl);
+        let broken = jsonl.replacen('\n', "\n\n{\"id\": \n", 1);
+        match from_str(&broken, None) {
+            Err(Error::Parse(message)) => assert!(message.contains("line 3"), "{}", message),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        assert!(from_str("[{\"id\": \"2018-05-01\"}]", None).is_err());
+        assert!(from_str("", None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+// failure_derive expands into named consts wrapping its impls
+#![allow(non_local_definitions)]
+
+#[macro_use] extern crate serde_derive;
+#[macro_use] extern crate failure;
+extern crate toml;
+extern crate serde_json;
+extern crate chrono;
+extern crate serde;
+extern crate rusqlite;
+extern crate ansi_term;
+extern crate pulldown_cmark;
+extern crate unicode_width;
+extern crate serde_yaml;
+extern crate regex;
+extern crate chacha20poly1305;
+extern crate argon2;
+extern crate rpassword;
+extern crate similar;
+extern crate crossterm;
+extern crate toml_edit;
+extern crate serde_ignored;
+
+pub mod page;
+pub mod config;
+pub mod utils;
+pub mod fsck;
+pub mod journal;
+pub mod lock;
+pub mod error;
+pub mod store;
+pub mod sqlite;
+pub mod index;
+pub mod date;
+pub mod calendar;
+pub mod html;
+pub mod markdown;
+pub mod json;
+pub mod import;
+pub mod crypto;
+pub mod git;
+pub mod revision;
+pub mod tui;
+pub mod template;
+pub mod quicklog;
@@ -0,0 +1,135 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+use error::Error;
+use utils::read_file;
+
+// File name of the lock taken while changing the diary
+pub const LOCK_FILENAME: &str = "LOCK";
+
+// Taken while removing a stale lock so that only one process removes it
+const BREAK_LOCK_FILENAME: &str = "LOCK.break";
+
+// Advisory lock of the app directory, released when dropped
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+}
+
+// Check if a process with the pid is running. A process of another user, which cannot be signaled,
+// is running too, so the process is regarded as running unless it is known not to exist.
+fn is_running(pid: u32) -> bool {
+    if cfg!(target_os = "windows") {
+        // Unable to check, so regard the lock as valid
+        true
+    } else {
+        Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .env("LC_ALL", "C")
+            .stdout(Stdio::null())
+            .output()
+            .map(|output| output.status.success() || !String::from_utf8_lossy(&output.stderr).contains("No such process"))
+            .unwrap_or(true)
+    }
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    read_file(&path.to_path_buf()).ok().and_then(|pid| pid.trim().parse::<u32>().ok())
+}
+
+// Remove the lock left by the crashed process. Another process may have replaced it with a new lock
+// since the pid was read, so it is read again while holding the break lock.
+fn remove_stale_lock(path: &Path, stale_pid: u32) -> Result<(), Error> {
+    let break_path = path.with_file_name(BREAK_LOCK_FILENAME);
+    match fs::OpenOptions::new().write(true).create_new(true).open(&break_path) {
+        Ok(_) => {},
+        Err(ref err) if err.kind() == ErrorKind::AlreadyExists => {
+            return Err(Error::Locked(format!("The diary is locked by another process. Remove `{}` if it is not running.", break_path.to_string_lossy())));
+        },
+        Err(err) => return Err(Error::io(format!("Unable to create lock file `{}`", break_path.to_string_lossy()), err)),
+    }
+
+    let result = match read_pid(path) {
+        Some(pid) if pid == stale_pid => match fs::remove_file(path) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result.map_err(|err| Error::io(format!("Unable to remove stale lock file `{}`", path.to_string_lossy()), err)),
+        },
+        // Already removed or taken by another process
+        _ => Ok(()),
+    };
+
+    let _ = fs::remove_file(&break_path);
+    result
+}
+
+impl Lock {
+    pub fn acquire(directory: &str) -> Result<Lock, Error> {
+        let path = Path::new(directory).join(LOCK_FILENAME);
+
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(process::id().to_string().as_bytes())
+                        .map_err(|err| Error::io(format!("Unable to write lock file `{}`", path.to_string_lossy()), err))?;
+
+                    return Ok(Lock { path });
+                },
+                Err(ref err) if err.kind() == ErrorKind::AlreadyExists => {
+                    match read_pid(&path) {
+                        // Remove the lock left by a crashed process and try again
+                        Some(pid) if !is_running(pid) => remove_stale_lock(&path, pid)?,
+                        Some(pid) => return Err(Error::Locked(format!("The diary is locked by another process (pid {}). Remove `{}` if it is not running.", pid, path.to_string_lossy()))),
+                        None => return Err(Error::Locked(format!("The diary is locked by another process. Remove `{}` if it is not running.", path.to_string_lossy()))),
+                    }
+                },
+                Err(err) => return Err(Error::io(format!("Unable to create lock file `{}`", path.to_string_lossy()), err)),
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use utils::write_file;
+
+    #[test]
+    fn acquire_and_break_stale_lock() {
+        let directory = env::temp_dir().join(format!("diary-lock-test-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let dir_str = directory.to_str().unwrap();
+        let path = directory.join(LOCK_FILENAME);
+
+        let lock = Lock::acquire(dir_str).unwrap();
+        assert_eq!(read_pid(&path), Some(process::id()));
+        assert!(matches!(Lock::acquire(dir_str), Err(Error::Locked(_))));
+        drop(lock);
+        assert!(!path.exists());
+
+        // The lock of an exited process is broken
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        assert!(!is_running(child.id()));
+        write_file(&path, &child.id().to_string()).unwrap();
+        let lock = Lock::acquire(dir_str).unwrap();
+        assert_eq!(read_pid(&path), Some(process::id()));
+        drop(lock);
+
+        // init is running whether or not it can be signaled by this user
+        assert!(is_running(1));
+        write_file(&path, "1").unwrap();
+        assert!(matches!(Lock::acquire(dir_str), Err(Error::Locked(_))));
+        assert!(path.exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,40 +1,95 @@
-#[macro_use] extern crate serde_derive;
-#[macro_use] extern crate failure;
-extern crate toml;
+extern crate diary;
 extern crate clap;
 extern crate chrono;
-extern crate serde;
 extern crate ansi_term;
 extern crate regex;
+extern crate terminal_size;
+extern crate toml;
 
 use std::fs;
-use std::io::Write;
 use std::path::Path;
-use std::env;
+use std::env::{self, VarError};
+use std::io;
+use std::io::Write;
+use std::process;
 
-use clap::{Arg, App, SubCommand};
+use clap::{Arg, App, AppSettings, ArgGroup, ArgMatches, SubCommand};
 
-use config::{Config};
+use diary::config::{Config};
+use diary::error::Error;
+use diary::lock::Lock;
+use diary::{git, journal, store, utils};
 
-mod page;
-mod config;
 mod commands;
-mod utils;
 
-use utils::{PAGES_DIR, HEAD_FILENAME};
+use diary::utils::{PAGES_DIR, HEAD_FILENAME};
 
-fn get_app_dir() -> Result<String, failure::Error> {
-    if cfg!(target_os = "windows") {
+fn get_app_dir() -> Result<String, Error> {
+    let var = |name: &str| env::var(name).map_err(|err| match err {
+        VarError::NotPresent => Error::NotFound(format!("Unable to find the app directory. set ${}", name)),
+        VarError::NotUnicode(_) => Error::Parse(format!("Unable to use the app directory. ${} is not valid UTF-8", name)),
+    });
+
+    let path = if cfg!(target_os = "windows") {
         // %LOCALAPPDATA%\diary
-        let val = env::var("LOCALAPPDATA")?;
-        let path = Path::new(&val).join("diary");
-        Ok(String::from(path.to_str().unwrap()))
+        Path::new(&var("LOCALAPPDATA")?).join("diary")
     } else {
         // $HOME/.config/diary
-        let val = env::var("HOME")?;
-        let path = Path::new(&val).join(".config").join("diary");
-        Ok(String::from(path.to_str().unwrap()))
+        Path::new(&var("HOME")?).join(".config").join("diary")
+    };
+
+    path.to_str()
+        .map(String::from)
+        .ok_or_else(|| Error::Parse(format!("Unable to use the app directory `{}` which is not valid UTF-8", path.to_string_lossy())))
+}
+
+fn ask(prompt: &str) -> Result<String, Error> {
+    print!("{}", prompt);
+    io::stdout().flush().map_err(|err| Error::io("Unable to flush stdout".to_string(), err))?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).map_err(|err| Error::io("Unable to read answer".to_string(), err))?;
+
+    Ok(answer.trim().to_string())
+}
+
+// Ask whether to recover temporary files left by crashed editors
+fn offer_stale_edit_files(app_dir: &str, config: &Config) -> Result<(), Error> {
+    for (path, id) in utils::find_stale_edit_files(app_dir)? {
+        let remove = |path: &Path| fs::remove_file(path)
+            .map_err(|err| Error::io(format!("Unable to remove `{}`", path.to_string_lossy()), err));
+
+        let id = match id {
+            Some(id) => id,
+            // Left by an older version which did not record the page id
+            None => {
+                let prompt = format!("Found an unsaved edit `{}` left by an older version. Enter the page id to recover it into, [d]iscard or nothing to decide later: ", path.to_string_lossy());
+                match ask(&prompt)?.as_str() {
+                    "" => {},
+                    "d" | "D" => remove(&path)?,
+                    id => {
+                        let mut store = store::open(app_dir, config)?;
+                        utils::recover_edit_file(store.as_mut(), app_dir, &path, id, &config.editor, &config.revisions)?;
+                    },
+                }
+                continue;
+            },
+        };
+
+        match ask(&format!("Found an unsaved edit of `{}` left by a crashed editor. Recover it? [y]es/[d]iscard/[L]ater: ", id))?.as_str() {
+            "y" | "Y" => {
+                let mut store = store::open(app_dir, config)?;
+                utils::recover_edit_file(store.as_mut(), app_dir, &path, &id, &config.editor, &config.revisions)?;
+            },
+            "d" | "D" => remove(&path)?,
+            _ => {
+                let recovery_path = utils::save_recovery(app_dir, &path, &id)?;
+                println!("Saved to `{}`. use `diary recover`", recovery_path.to_string_lossy());
+            },
+        }
     }
+
+    Ok(())
 }
 
 fn main() {
@@ -56,20 +111,118 @@ fn main() {
                     .arg(Arg::with_name("skip")
                          .takes_value(true)
                          .long("skip")
-                         .help("skip")))
+                         .help("skip"))
+                    .arg(Arg::with_name("since")
+                         .takes_value(true)
+                         .long("since")
+                         .value_name("YYYY-MM-DD")
+                         .help("list pages on or after the date"))
+                    .arg(Arg::with_name("until")
+                         .takes_value(true)
+                         .long("until")
+                         .value_name("YYYY-MM-DD")
+                         .help("list pages on or before the date"))
+                    .arg(Arg::with_name("month")
+                         .takes_value(true)
+                         .long("month")
+                         .value_name("YYYY-MM")
+                         .help("list pages in the month"))
+                    .arg(Arg::with_name("year")
+                         .takes_value(true)
+                         .long("year")
+                         .value_name("YYYY")
+                         .help("list pages in the year"))
+                    .arg(Arg::with_name("last")
+                         .takes_value(true)
+                         .long("last")
+                         .value_name("PERIOD")
+                         .help("list pages in the last period like 3d, 2w, 1m or 1y"))
+                    .arg(Arg::with_name("tag")
+                         .takes_value(true)
+                         .long("tag")
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("list pages with the tag")))
         .subcommand(SubCommand::with_name("new")
                     .arg(Arg::with_name("id")
                          .index(1))
+                    .arg(Arg::with_name("template")
+                         .takes_value(true)
+                         .long("template")
+                         .short("t")
+                         .help("start with `templates/<NAME>.md` instead of the default template in config.toml"))
+                    .arg(Arg::with_name("message")
+                         .takes_value(true)
+                         .long("message")
+                         .short("m")
+                         .conflicts_with("stdin")
+                         .help("create the page with the text without opening the editor"))
+                    .arg(Arg::with_name("stdin")
+                         .long("stdin")
+                         .help("create the page with the text read from stdin without opening the editor"))
+                    .arg(Arg::with_name("title")
+                         .takes_value(true)
+                         .long("title")
+                         .help("title of the page"))
+                    .arg(Arg::with_name("memo")
+                         .long("memo")
+                         .help("create the page as a memo even if the id is not given"))
                     .about("create new page"))
+        .subcommand(SubCommand::with_name("append")
+                    .arg(Arg::with_name("id")
+                         .index(1))
+                    .arg(Arg::with_name("message")
+                         .takes_value(true)
+                         .long("message")
+                         .short("m")
+                         .help("text to append"))
+                    .arg(Arg::with_name("stdin")
+                         .long("stdin")
+                         .help("append the text read from stdin"))
+                    .group(ArgGroup::with_name("text")
+                           .args(&["message", "stdin"])
+                           .required(true))
+                    .about("append text to the page without opening the editor. today's page by default"))
         .subcommand(SubCommand::with_name("edit")
                     .arg(Arg::with_name("id")
                          .index(1))
                     .about("edit page"))
         .subcommand(SubCommand::with_name("config")
+                    .subcommand(SubCommand::with_name("get")
+                                .arg(Arg::with_name("key")
+                                     .index(1)
+                                     .required(true))
+                                .about("print the value of the setting, like `revisions.max_count`"))
+                    .subcommand(SubCommand::with_name("set")
+                                .arg(Arg::with_name("key")
+                                     .index(1)
+                                     .required(true))
+                                .arg(Arg::with_name("value")
+                                     .index(2)
+                                     .required(true))
+                                .about("change the setting in config.toml"))
+                    .subcommand(SubCommand::with_name("list")
+                                .about("print all settings including defaults"))
                     .about("edit config file"))
         .subcommand(SubCommand::with_name("show")
                     .arg(Arg::with_name("id")
                          .index(1))
+                    .arg(Arg::with_name("markdown")
+                         .long("markdown")
+                         .short("m")
+                         .help("render the page as Markdown"))
+                    .arg(Arg::with_name("no-color")
+                         .long("no-color")
+                         .help("disable color"))
+                    .arg(Arg::with_name("pager")
+                         .long("pager")
+                         .short("p")
+                         .help("show the page with $PAGER"))
+                    .arg(Arg::with_name("rev")
+                         .takes_value(true)
+                         .long("rev")
+                         .short("r")
+                         .help("show the revision of the number in `diary history`"))
                     .about("show detail page"))
         .subcommand(SubCommand::with_name("search")
                     .arg(Arg::with_name("query")
@@ -79,6 +232,28 @@ fn main() {
                         .long("title")
                         .short("t")
                         .help("search by the title only"))
+                    .arg(Arg::with_name("regex")
+                        .long("regex")
+                        .short("E")
+                        .help("match the query as a regular expression against each page"))
+                    .arg(Arg::with_name("ignore-case")
+                        .long("ignore-case")
+                        .short("i")
+                        .help("match the query ignoring case against each page"))
+                    .arg(Arg::with_name("word")
+                        .long("word")
+                        .short("w")
+                        .help("match the query as a whole word against each page"))
+                    .arg(Arg::with_name("context")
+                        .takes_value(true)
+                        .short("C")
+                        .help("print matched lines with N lines around them"))
+                    .arg(Arg::with_name("tag")
+                        .takes_value(true)
+                        .long("tag")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("search pages with the tag"))
                     .about("search from all pages"))
         .subcommand(SubCommand::with_name("editid")
                     .arg(Arg::with_name("prev_id")
@@ -88,91 +263,281 @@ fn main() {
                          .index(2)
                          .required(true))
                     .about("edit id"))
+        .subcommand(SubCommand::with_name("rm")
+                    .arg(Arg::with_name("id")
+                         .index(1)
+                         .required(true))
+                    .arg(Arg::with_name("trash")
+                         .long("trash")
+                         .help("move the page into the trash directory instead of deleting"))
+                    .about("remove page"))
+        .subcommand(SubCommand::with_name("fsck")
+                    .arg(Arg::with_name("repair")
+                         .long("repair")
+                         .help("rebuild the chain ordered by created time"))
+                    .about("verify the page chain"))
+        .subcommand(SubCommand::with_name("recover")
+                    .arg(Arg::with_name("name")
+                         .index(1)
+                         .help("recovery file name or page id"))
+                    .arg(Arg::with_name("discard")
+                         .long("discard")
+                         .help("remove the recovery file"))
+                    .about("list or reopen unsaved edits"))
+        .subcommand(SubCommand::with_name("cal")
+                    .arg(Arg::with_name("month")
+                         .index(1)
+                         .value_name("YYYY-MM")
+                         .help("month to show instead of this month"))
+                    .arg(Arg::with_name("year")
+                         .takes_value(true)
+                         .long("year")
+                         .short("y")
+                         .value_name("YYYY")
+                         .conflicts_with("month")
+                         .help("show all months of the year"))
+                    .arg(Arg::with_name("prev")
+                         .long("prev")
+                         .short("p")
+                         .multiple(true)
+                         .help("show the previous month. repeat to go back further"))
+                    .arg(Arg::with_name("next")
+                         .long("next")
+                         .short("n")
+                         .multiple(true)
+                         .help("show the next month. repeat to go further"))
+                    .about("show a calendar of pages"))
+        .subcommand(SubCommand::with_name("tags")
+                    .about("list tags with the number of pages"))
+        .subcommand(SubCommand::with_name("migrate")
+                    .arg(Arg::with_name("force")
+                         .long("force")
+                         .help("overwrite pages already in the database"))
+                    .about("copy pages into the SQLite database"))
+        .subcommand(SubCommand::with_name("log")
+                    .arg(Arg::with_name("message")
+                         .index(1)
+                         .multiple(true)
+                         .required_unless("show")
+                         .help("message to append to today's page.\n\
+                                the id of an existing page shows commits changing it with `git = true`"))
+                    .arg(Arg::with_name("show")
+                         .long("show")
+                         .short("s")
+                         .conflicts_with("message")
+                         .help("print today's log lines"))
+                    .about("append a line with the time to today's page, or show commits changing the page"))
+        .subcommand(SubCommand::with_name("diff")
+                    .arg(Arg::with_name("id")
+                         .index(1)
+                         .required(true))
+                    .arg(Arg::with_name("rev")
+                         .takes_value(true)
+                         .long("rev")
+                         .short("r")
+                         .help("revisions to compare like `2..5`, or `2` to compare with the current version.\n\
+                                a git commit or range like `HEAD~3..HEAD~1` with `git = true`.\n\
+                                the last change by default"))
+                    .arg(Arg::with_name("no-color")
+                         .long("no-color")
+                         .help("disable color"))
+                    .about("show changes of the page"))
+        .subcommand(SubCommand::with_name("history")
+                    .arg(Arg::with_name("id")
+                         .index(1)
+                         .required(true))
+                    .about("list revisions of the page"))
+        .subcommand(SubCommand::with_name("revert")
+                    .arg(Arg::with_name("id")
+                         .index(1)
+                         .required(true))
+                    .arg(Arg::with_name("rev")
+                         .takes_value(true)
+                         .long("rev")
+                         .short("r")
+                         .required(true)
+                         .help("number of the revision in `diary history`"))
+                    .about("restore the page to the revision. the current version is kept as a revision"))
+        .subcommand(SubCommand::with_name("sync")
+                    .about("pull from and push to `git_remote`"))
+        .subcommand(SubCommand::with_name("tui")
+                    .about("browse and edit pages in a full-screen interface"))
+        .subcommand(SubCommand::with_name("encrypt")
+                    .arg(Arg::with_name("purge-history")
+                         .long("purge-history")
+                         .help("remove the git history, which keeps pages in plain text"))
+                    .about("encrypt all pages with a passphrase. set DIARY_PASSPHRASE to skip prompting"))
+        .subcommand(SubCommand::with_name("decrypt")
+                    .about("decrypt all pages and disable encryption"))
+        .subcommand(SubCommand::with_name("export")
+                    .setting(AppSettings::ArgsNegateSubcommands)
+                    .arg(Arg::with_name("format")
+                         .takes_value(true)
+                         .long("format")
+                         .short("f")
+                         .possible_values(&["json", "jsonl"])
+                         .help("write all pages in JSON or JSON Lines"))
+                    .arg(Arg::with_name("output")
+                         .takes_value(true)
+                         .long("output")
+                         .short("o")
+                         .value_name("FILE")
+                         .help("file to write instead of stdout"))
+                    .subcommand(SubCommand::with_name("pages")
+                                .arg(Arg::with_name("outdir")
+                                     .index(1)
+                                     .required(true))
+                                .about("write pages in the .page format"))
+                    .subcommand(SubCommand::with_name("html")
+                                .arg(Arg::with_name("outdir")
+                                     .index(1)
+                                     .required(true))
+                                .about("render pages into a static HTML site"))
+                    .about("export pages"))
+        .subcommand(SubCommand::with_name("import")
+                    .arg(Arg::with_name("file")
+                         .index(1)
+                         .required(true)
+                         .help("file exported by `diary export --format` or another journaling tool, or - for stdin"))
+                    .arg(Arg::with_name("format")
+                         .takes_value(true)
+                         .long("format")
+                         .short("f")
+                         .possible_values(&["json", "jsonl", "markdown", "dayone", "jrnl"])
+                         .help("format of the file. json or jsonl is detected from the contents by default.\n\
+                                markdown: directory of Markdown files with YAML front matter\n\
+                                dayone: JSON exported by Day One\n\
+                                jrnl: plain text exported by jrnl"))
+                    .about("add pages from JSON, JSON Lines or other journaling tools"))
         .get_matches();
 
+    if let Err(err) = run(&matches) {
+        eprintln!("{}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
     // Load config
-    let app_dir = get_app_dir().unwrap();
+    let app_dir = get_app_dir()?;
 
     // create config.toml and `pages` directory and head file if app directory does not exists
     let app_dir_path = Path::new(&app_dir);
     let config_path = app_dir_path.join("config.toml");
     if !app_dir_path.exists() {
         // create app directory
-        if let Err(err) = fs::create_dir(app_dir_path) {
-            println!("Unable to create directory `{}`: {}", app_dir_path.to_string_lossy(), err);
-            return;
-        }
+        fs::create_dir(app_dir_path)
+            .map_err(|err| Error::io(format!("Unable to create directory `{}`", app_dir_path.to_string_lossy()), err))?;
 
         // create config
-        let initial_config_toml = "editor = 'vim'\nlist_max_count = 7";
-        let mut config_file = match fs::File::create(&config_path) {
-            Ok(file) => file,
-            Err(err) => {
-                println!("Unable to create config `{}`: {}", config_path.to_string_lossy(), err);
-                return;
-            },
-        };
-
-        if let Err(err) = config_file.write_all(initial_config_toml.as_bytes()) {
-            println!("Unable to write initial config toml: {}", err);
-            return;
-        }
+        let initial_config_toml = "# Every setting is optional. `diary config list` shows the current values.\n# editor = 'vim'\n# list_max_count = 7\n";
+        utils::write_file(&config_path, initial_config_toml)
+            .map_err(|err| Error::io(format!("Unable to create config `{}`", config_path.to_string_lossy()), err))?;
 
         // create pages directory
         let pages_path = app_dir_path.join(PAGES_DIR);
-        if let Err(err) = fs::create_dir(&pages_path) {
-            println!("Unable to create directory `{}`: {}", pages_path.to_string_lossy(), err);
-            return;
-        }
+        fs::create_dir(&pages_path)
+            .map_err(|err| Error::io(format!("Unable to create directory `{}`", pages_path.to_string_lossy()), err))?;
 
-        // create config
-        let initial_head = "NULL";
+        // create head file
         let head_path = app_dir_path.join(HEAD_FILENAME);
-        let mut head_file = match fs::File::create(&head_path) {
-            Ok(file) => file,
-            Err(err) => {
-                println!("Unable to create head file `{}`: {}", head_path.to_string_lossy(), err);
-                return;
-            },
-        };
-
-        if let Err(err) = head_file.write_all(initial_head.as_bytes()) {
-            println!("Unable to write initial head id: {}", err);
-            return;
-        }
+        utils::write_file(&head_path, "NULL")
+            .map_err(|err| Error::io(format!("Unable to create head file `{}`", head_path.to_string_lossy()), err))?;
     }
 
     let config_path = config_path.as_path();
-    let config = match Config::load_from_file(config_path) {
-        Ok(config) => config,
+    let (config, unknown_keys) = Config::load_from_file(config_path)
+        .map_err(|err| Error::Parse(format!("Failed to load config '{}': {}", config_path.to_string_lossy(), err)))?;
+    for key in unknown_keys {
+        eprintln!("Warning: unknown key `{}` in '{}'", key, config_path.to_string_lossy());
+    }
+
+    let name = matches.subcommand_name();
+
+    // Take the lock for commands changing the diary. Other commands run without it if it is taken.
+    let is_mutating = match matches.subcommand() {
+        ("log", Some(matches)) => !matches.is_present("show"),
+        // `config set` reads and writes config.toml
+        ("config", Some(matches)) => matches.subcommand_name() == Some("set"),
+        _ => matches!(name, None | Some("new") | Some("append") | Some("edit") | Some("editid") | Some("rm") | Some("fsck") | Some("recover") | Some("migrate") | Some("import") | Some("encrypt") | Some("decrypt") | Some("sync") | Some("revert") | Some("tui")),
+    };
+
+    // Fail before changing anything that could not be recorded. `config set` may be fixing the config.
+    if is_mutating && config.git && name != Some("config") {
+        git::ensure_supported(&app_dir, &config)?;
+    }
+
+    let lock = match Lock::acquire(&app_dir) {
+        Ok(lock) => Some(lock),
         Err(err) => {
-            println!("Failed to load config '{}': {}", config_path.to_string_lossy(), err);
-            return;
+            if is_mutating {
+                return Err(err);
+            }
+            None
         },
     };
 
-    let name = matches.subcommand_name();
+    // Finish an update interrupted last time
+    if lock.is_some() && journal::recover(&app_dir)? {
+        eprintln!("Recovered an interrupted update");
+    }
+
+    // Commands used from scripts must not wait for an answer
+    let is_interactive = match matches.subcommand() {
+        ("new", Some(matches)) => !matches.is_present("message") && !matches.is_present("stdin"),
+        ("import", Some(matches)) => matches.value_of("file") != Some("-"),
+        ("append", _) | ("log", _) | ("config", _) => false,
+        _ => true,
+    };
+
+    let _lock = if is_mutating {
+        if is_interactive {
+            offer_stale_edit_files(&app_dir, &config)?;
+        }
+        lock
+    } else {
+        // Read-only commands do not block writers while they run
+        drop(lock);
+        None
+    };
+
     let func = match name {
         Some("ls") => commands::list,
         Some("new") => commands::create_new,
+        Some("append") => commands::append,
         Some("edit") => commands::edit,
         Some("config") => commands::config,
         Some("show") => commands::show,
         Some("search") => commands::search,
         Some("editid") => commands::editid,
+        Some("rm") => commands::remove,
+        Some("fsck") => commands::fsck,
+        Some("recover") => commands::recover,
+        Some("cal") => commands::cal,
+        Some("tags") => commands::tags,
+        Some("migrate") => commands::migrate,
+        Some("export") => commands::export,
+        Some("import") => commands::import,
+        Some("encrypt") => commands::encrypt,
+        Some("decrypt") => commands::decrypt,
+        Some("log") => commands::log,
+        Some("diff") => commands::diff,
+        Some("sync") => commands::sync,
+        Some("history") => commands::history,
+        Some("revert") => commands::revert,
+        Some("tui") => commands::tui,
         _ => commands::diary,
     };
 
-    if name == None {
-        if let Err(message) = func(&app_dir, &config, &matches) {
-            println!("{}", message);
-        }
-        return;
+    match name {
+        Some(name) => func(&app_dir, &config, matches.subcommand_matches(name).unwrap())?,
+        None => func(&app_dir, &config, matches)?,
     }
 
-    if let Some(matches) = matches.subcommand_matches(name.unwrap()) {
-        if let Err(message) = func(&app_dir, &config, &matches) {
-            println!("{}", message);
-        }
+    // Record the changes in the history
+    if is_mutating && config.git {
+        git::commit(&app_dir)?;
     }
+
+    Ok(())
 }
@@ -0,0 +1,340 @@
+use ansi_term::Colour::{Blue, Cyan, Fixed, Yellow};
+use ansi_term::Style;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+// Width used when the terminal width is unknown
+pub const DEFAULT_WIDTH: usize = 80;
+
+// Prefix of lines in a block quote or a list item
+struct Container {
+    first: String,
+    rest: String,
+    used: bool,
+}
+
+// Markdown renderer for terminals
+struct Renderer {
+    width: usize,
+    color: bool,
+    lines: Vec<String>,
+    containers: Vec<Container>,
+    styles: Vec<Style>,
+    // Numbers of the next items of ordered lists, or `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    links: Vec<String>,
+    line: String,
+    line_width: usize,
+    // Width of the prefix of the current line
+    prefix_width: usize,
+    space: bool,
+    blank: bool,
+    code_block: bool,
+}
+
+// Merge styles of nested elements
+fn merge(styles: &[Style]) -> Style {
+    styles.iter().fold(Style::new(), |mut merged, style| {
+        if style.foreground.is_some() {
+            merged.foreground = style.foreground;
+        }
+        merged.is_bold |= style.is_bold;
+        merged.is_italic |= style.is_italic;
+        merged.is_underline |= style.is_underline;
+        merged.is_dimmed |= style.is_dimmed;
+        merged.is_strikethrough |= style.is_strikethrough;
+        merged
+    })
+}
+
+// Kana, CJK ideographs and Hangul, which can be broken between any characters
+fn is_wide(c: char) -> bool {
+    c.width().unwrap_or(0) > 1
+}
+
+impl Renderer {
+    fn new(width: usize, color: bool) -> Renderer {
+        Renderer {
+            width,
+            color,
+            lines: Vec::new(),
+            containers: Vec::new(),
+            styles: Vec::new(),
+            lists: Vec::new(),
+            links: Vec::new(),
+            line: String::new(),
+            line_width: 0,
+            prefix_width: 0,
+            space: false,
+            blank: false,
+            code_block: false,
+        }
+    }
+
+    fn paint(&self, s: &str, style: Style) -> String {
+        if self.color && !s.is_empty() {
+            style.paint(s).to_string()
+        } else {
+            s.to_string()
+        }
+    }
+
+    fn start_line(&mut self) {
+        let mut prefix = String::new();
+        for container in &mut self.containers {
+            if container.used {
+                prefix.push_str(&container.rest);
+            } else {
+                prefix.push_str(&container.first);
+                container.used = true;
+            }
+        }
+
+        self.prefix_width = prefix.width();
+        self.line_width = self.prefix_width;
+        self.line = self.paint(&prefix, Fixed(244).normal());
+    }
+
+    fn end_line(&mut self) {
+        if self.line_width > 0 || !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.lines.push(line);
+        }
+        self.line_width = 0;
+        self.space = false;
+    }
+
+    // Start a block, separated from the previous block by a blank line
+    fn start_block(&mut self) {
+        self.end_line();
+        if self.blank {
+            let prefix: String = self.containers.iter().map(|container| container.rest.as_str()).collect();
+            self.lines.push(self.paint(prefix.trim_end(), Fixed(244).normal()));
+            self.blank = false;
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.end_line();
+        self.blank = true;
+    }
+
+    fn push_word(&mut self, word: &str, style: Style) {
+        let width = word.width();
+        let space = if self.space && self.line_width > self.prefix_width { 1 } else { 0 };
+
+        if self.line_width == 0 {
+            self.start_line();
+        } else if self.line_width + space + width > self.width && self.line_width > self.prefix_width {
+            self.end_line();
+            self.start_line();
+        } else if space > 0 {
+            self.line.push(' ');
+            self.line_width += 1;
+        }
+
+        self.line.push_str(&self.paint(word, style));
+        self.line_width += width;
+        self.space = false;
+    }
+
+    // Push text broken at spaces and between wide characters
+    fn push_text(&mut self, text: &str) {
+        let style = merge(&self.styles);
+
+        let mut word = String::new();
+        for c in text.chars() {
+            if (c.is_whitespace() || is_wide(c)) && !word.is_empty() {
+                self.push_word(&word, style);
+                word.clear();
+            }
+
+            if c.is_whitespace() {
+                self.space = true;
+            } else if is_wide(c) {
+                self.push_word(&c.to_string(), style);
+            } else {
+                word.push(c);
+            }
+        }
+
+        if !word.is_empty() {
+            self.push_word(&word, style);
+        }
+    }
+
+    fn push_code_block(&mut self, text: &str) {
+        for line in text.lines() {
+            self.start_line();
+            let code = self.paint(&format!("    {}", line), Cyan.normal());
+            self.line.push_str(&code);
+            self.line_width += 1;
+            self.end_line();
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.start_block(),
+            Tag::Heading(level, _, _) => {
+                self.start_block();
+                let (style, marker) = match level {
+                    HeadingLevel::H1 => (Yellow.bold().underline(), "#"),
+                    HeadingLevel::H2 => (Yellow.bold(), "##"),
+                    HeadingLevel::H3 => (Style::new().bold(), "###"),
+                    _ => (Style::new().bold(), "####"),
+                };
+                self.styles.push(style);
+                // Headings are distinguished only by markers without colors
+                if !self.color {
+                    self.push_text(marker);
+                    self.space = true;
+                }
+            },
+            Tag::BlockQuote => {
+                self.start_block();
+                self.containers.push(Container { first: "│ ".to_string(), rest: "│ ".to_string(), used: false });
+                self.styles.push(Style::new().italic());
+            },
+            Tag::CodeBlock(kind) => {
+                self.start_block();
+                if let CodeBlockKind::Fenced(lang) = kind {
+                    if !lang.is_empty() {
+                        self.start_line();
+                        let lang = self.paint(&format!("    [{}]", lang), Fixed(244).normal());
+                        self.line.push_str(&lang);
+                        self.line_width += 1;
+                        self.end_line();
+                    }
+                }
+                self.code_block = true;
+            },
+            Tag::List(start) => {
+                if self.containers.is_empty() {
+                    self.start_block();
+                } else {
+                    self.end_line();
+                }
+                self.lists.push(start);
+            },
+            Tag::Item => {
+                self.end_line();
+                self.blank = false;
+
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    },
+                    _ => "• ".to_string(),
+                };
+                let rest = " ".repeat(bullet.width());
+                self.containers.push(Container { first: bullet, rest, used: false });
+            },
+            Tag::Emphasis => self.styles.push(Style::new().italic()),
+            Tag::Strong => self.styles.push(Style::new().bold()),
+            Tag::Strikethrough => self.styles.push(Style::new().strikethrough()),
+            Tag::Link(_, url, _) | Tag::Image(_, url, _) => {
+                self.styles.push(Blue.underline());
+                self.links.push(url.to_string());
+            },
+            _ => {},
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.end_block(),
+            Tag::Heading(..) => {
+                self.styles.pop();
+                self.end_block();
+            },
+            Tag::BlockQuote => {
+                self.styles.pop();
+                self.end_line();
+                self.containers.pop();
+                self.blank = true;
+            },
+            Tag::CodeBlock(_) => {
+                self.code_block = false;
+                self.end_block();
+            },
+            Tag::List(_) => {
+                self.lists.pop();
+                self.end_line();
+                self.blank = self.containers.is_empty();
+            },
+            Tag::Item => {
+                self.end_line();
+                self.containers.pop();
+            },
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough => { self.styles.pop(); },
+            Tag::Link(..) | Tag::Image(..) => {
+                self.styles.pop();
+                if let Some(url) = self.links.pop() {
+                    self.space = true;
+                    self.push_word(&format!("({})", url), Fixed(244).normal());
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) if self.code_block => self.push_code_block(&text),
+            Event::Text(text) | Event::Html(text) => self.push_text(&text),
+            Event::Code(code) => {
+                let style = merge(&self.styles).fg(Cyan);
+                for (i, word) in code.split(' ').enumerate() {
+                    if i > 0 {
+                        self.space = true;
+                    }
+                    self.push_word(word, style);
+                }
+            },
+            Event::SoftBreak => self.space = true,
+            Event::HardBreak => self.end_line(),
+            Event::Rule => {
+                self.start_block();
+                self.start_line();
+                let rule = "─".repeat(self.width.saturating_sub(self.line_width));
+                let rule = self.paint(&rule, Fixed(244).normal());
+                self.line.push_str(&rule);
+                self.line_width += 1;
+                self.end_block();
+            },
+            Event::TaskListMarker(checked) => {
+                self.push_word(if checked { "[x]" } else { "[ ]" }, Style::new());
+                self.space = true;
+            },
+            _ => {},
+        }
+    }
+}
+
+// Render Markdown with ANSI styles, wrapped to the width
+pub fn render(text: &str, width: usize, color: bool) -> String {
+    let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut renderer = Renderer::new(width, color);
+    for event in Parser::new_ext(text, options) {
+        renderer.event(event);
+    }
+    renderer.end_line();
+
+    renderer.lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_plain() {
+        let text = "# Title\n\nsome *words* to wrap here\n\n- one\n- two\n  1. nested\n\n> quote\n\n```\ncode\n```";
+        let expected = "# Title\n\nsome words\nto wrap\nhere\n\n• one\n• two\n  1. nested\n\n│ quote\n\n    code";
+        assert_eq!(render(text, 10, false), expected);
+    }
+}
@@ -2,6 +2,7 @@ use toml;
 use chrono::prelude::{DateTime, Utc};
 
 #[derive(Debug, Fail)]
+#[allow(clippy::enum_variant_names)]
 pub enum PageError {
     #[fail(display = "Failed to parse page: {}", _0)]
     ParseError(String),
@@ -29,18 +30,20 @@ impl From<toml::ser::Error> for PageError {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PageHeader {
     pub title: String,
     pub insert_title: bool,
     pub created: DateTime<Utc>,
     pub updated: Vec<DateTime<Utc>>,
     pub memo: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
     pub prev: String,
     pub next: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Page {
     pub id: String,
     pub header: PageHeader,
@@ -73,6 +76,46 @@ impl Page {
 
         Ok(format!("---\n{}---\n{}", header_toml, self.text))
     }
+
+    // Tags in the header and `#tag` in the text
+    pub fn tags(&self) -> Vec<String> {
+        let mut tags = self.header.tags.clone();
+        for tag in inline_tags(&self.text) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        tags
+    }
+
+    // Check if the page has all of the tags
+    pub fn has_tags(&self, tags: &[&str]) -> bool {
+        let page_tags = self.tags();
+        tags.iter().all(|tag| page_tags.iter().any(|page_tag| page_tag == tag))
+    }
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '/'
+}
+
+// `#tag` at the start of the text or after a whitespace. `# heading` is not a tag.
+pub fn inline_tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    let mut prev = None;
+
+    for (i, c) in text.char_indices() {
+        if c == '#' && prev.map(|prev: char| prev.is_whitespace()).unwrap_or(true) {
+            let tag: String = text[i + 1..].chars().take_while(|&c| is_tag_char(c)).collect();
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        prev = Some(c);
+    }
+
+    tags
 }
 
 #[cfg(test)]
@@ -87,9 +130,10 @@ mod tests {
             header: PageHeader {
                 title: "taitoru".to_string(),
                 insert_title: true,
-                created: Utc.ymd(2018, 8, 15).and_hms(17, 52, 11),
-                updated: vec![Utc.ymd(2018, 8, 15).and_hms(17, 52, 44)],
+                created: Utc.with_ymd_and_hms(2018, 8, 15, 17, 52, 11).unwrap(),
+                updated: vec![Utc.with_ymd_and_hms(2018, 8, 15, 17, 52, 44).unwrap()],
                 memo: true,
+                tags: Vec::new(),
                 prev: "NULL".to_string(),
                 next: "NULL".to_string(),
             },
@@ -109,4 +153,10 @@ next = "NULL"
 
         assert_eq!(page.to_str().unwrap(), expected);
     }
+
+    #[test]
+    fn inline_tags_in_text() {
+        let text = "# Heading\n#work and #rust/cli, not a#b or ##c\n#work";
+        assert_eq!(inline_tags(text), vec!["work", "rust/cli"]);
+    }
 }
@@ -0,0 +1,55 @@
+use chrono::{NaiveDate, NaiveTime};
+use regex::Regex;
+
+use date::DIARY_ID_FORMAT;
+use error::Error;
+use store::Store;
+
+// Line added by `diary log`, like `- 14:32 deployed v2`. Following lines of the message are
+// indented to continue the list item. Returns `None` if the message is empty.
+pub fn entry(time: &NaiveTime, message: &str) -> Option<String> {
+    let message = message.trim();
+    if message.is_empty() {
+        return None;
+    }
+
+    Some(format!("- {} {}", time.format("%H:%M"), message.replace('\n', "\n  ")))
+}
+
+// Lines added by `diary log` to the page of the date
+pub fn lines(store: &dyn Store, date: &NaiveDate) -> Result<Vec<String>, Error> {
+    let id = date.format(DIARY_ID_FORMAT).to_string();
+    if !store.exists(&id)? {
+        return Ok(Vec::new());
+    }
+
+    let re = Regex::new(r"^- \d{2}:\d{2}( |$)").unwrap();
+    let page = store.get(&id)?;
+    Ok(page.text.lines().filter(|line| re.is_match(line)).map(|line| line.to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use store::MemoryStore;
+    use utils::new_page;
+
+    #[test]
+    fn entries_of_the_date() {
+        let time = NaiveTime::from_hms_opt(14, 32, 5).unwrap();
+        assert_eq!(entry(&time, " deployed v2\n").unwrap(), "- 14:32 deployed v2");
+        assert_eq!(entry(&time, "deployed\nv2").unwrap(), "- 14:32 deployed\n  v2");
+        assert_eq!(entry(&time, "  "), None);
+
+        let mut store = MemoryStore::new();
+        for &(id, text) in &[("2018-05-01", "- 09:00 old"), ("2018-05-02", "# Notes\n- 10:15 first\n- item\n- 11:00 second")] {
+            let mut page = new_page(id);
+            page.text = text.to_string();
+            store.insert(page).unwrap();
+        }
+
+        let date = NaiveDate::from_ymd_opt(2018, 5, 2).unwrap();
+        assert_eq!(lines(&store, &date).unwrap(), vec!["- 10:15 first", "- 11:00 second"]);
+        assert!(lines(&store, &NaiveDate::from_ymd_opt(2018, 5, 3).unwrap()).unwrap().is_empty());
+    }
+}
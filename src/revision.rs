@@ -0,0 +1,187 @@
+use ansi_term::Colour::{Cyan, Green, Red};
+use ansi_term::Style;
+use chrono::{DateTime, Duration, Utc};
+use similar::TextDiff;
+
+use config::Retention;
+use error::Error;
+use page::Page;
+use store::{Change, Store};
+use utils::TemporaryPage;
+
+// Time the version of the page was saved
+pub fn saved_time(page: &Page) -> DateTime<Utc> {
+    page.header.updated.last().cloned().unwrap_or(page.header.created)
+}
+
+// Contents compared between versions, which are the contents shown in the editor
+pub fn contents(page: &Page) -> Result<String, Error> {
+    Ok(TemporaryPage::from_page(page).to_str()?)
+}
+
+// Numbers of revisions removed by the policy. `revisions` are pairs of the number and the saved
+// time in ascending order. The newest revision is never removed by its age.
+fn expired(revisions: &[(u32, DateTime<Utc>)], retention: &Retention, now: DateTime<Utc>) -> Vec<u32> {
+    let keep_from = revisions.len().saturating_sub(retention.max_count as usize);
+    let too_old = |saved: DateTime<Utc>| retention.max_days.map(|days| now - saved > Duration::days(days.into())).unwrap_or(false);
+
+    revisions.iter().enumerate()
+        .filter(|&(i, &(_, saved))| i < keep_from || (i + 1 < revisions.len() && too_old(saved)))
+        .map(|(_, &(number, _))| number)
+        .collect()
+}
+
+// Changes saving the edited page. The previous version is kept as a new revision if the contents
+// have changed, and old revisions are removed by the policy.
+pub fn save_changes(store: &dyn Store, previous: &Page, page: &Page, retention: &Retention) -> Result<Vec<Change>, Error> {
+    let mut changes = vec![Change::Put(page.clone())];
+    if retention.max_count == 0 || contents(previous)? == contents(page)? {
+        return Ok(changes);
+    }
+
+    let mut revisions = Vec::new();
+    for number in store.revisions(&page.id)? {
+        revisions.push((number, saved_time(&store.get_revision(&page.id, number)?)));
+    }
+
+    let number = revisions.last().map(|&(number, _)| number + 1).unwrap_or(1);
+    revisions.push((number, saved_time(previous)));
+    changes.push(Change::PutRevision(number, previous.clone()));
+
+    for expired in expired(&revisions, retention, Utc::now()) {
+        changes.push(Change::RemoveRevision(page.id.clone(), expired));
+    }
+
+    Ok(changes)
+}
+
+// The page with the contents of the revision, keeping links and the created time
+pub fn restore(page: &Page, revision: &Page) -> Page {
+    let mut restored = page.clone();
+    TemporaryPage::from_page(revision).apply(&mut restored);
+    restored.header.updated.push(Utc::now());
+    restored
+}
+
+// Parse `A..B`, `A..` or `A` into revision numbers. `None` is the current version.
+pub fn parse_range(s: &str) -> Result<(u32, Option<u32>), Error> {
+    let invalid = || Error::Parse(format!("Invalid revision range `{}`. use `A..B`, `A..` or `A`", s));
+    let number = |s: &str| s.parse::<u32>().map_err(|_| invalid());
+
+    match s.find("..") {
+        Some(pos) if pos + 2 == s.len() => Ok((number(&s[..pos])?, None)),
+        Some(pos) => Ok((number(&s[..pos])?, Some(number(&s[pos + 2..])?))),
+        None => Ok((number(s)?, None)),
+    }
+}
+
+// Versions compared by `diary diff`
+#[derive(Debug, PartialEq)]
+pub enum DiffSource<'a> {
+    // Revision numbers. `None` is the current version.
+    Revisions(u32, Option<u32>),
+    // Commits of the git history. `None` is the last change.
+    Git(Option<&'a str>),
+}
+
+// Decide what to compare by `--rev`. Revision numbers of the page are preferred, and other revisions
+// like `1a0868d..HEAD` are git commits if the history is enabled. `numbers` are revisions of the page.
+pub fn diff_source<'a>(id: &str, rev: Option<&'a str>, numbers: &[u32], has_git: bool) -> Result<DiffSource<'a>, Error> {
+    match rev {
+        Some(rev) => {
+            let exists = |number: u32| numbers.contains(&number);
+            match parse_range(rev) {
+                Ok((from, to)) if !has_git || (exists(from) && to.is_none_or(exists)) => Ok(DiffSource::Revisions(from, to)),
+                Err(err) if !has_git => Err(err),
+                _ => Ok(DiffSource::Git(Some(rev))),
+            }
+        },
+        None => match numbers.last() {
+            Some(&last) => Ok(DiffSource::Revisions(last, None)),
+            None if has_git => Ok(DiffSource::Git(None)),
+            None => Err(Error::NotFound(format!("`{}` has no revisions", id))),
+        },
+    }
+}
+
+// Unified diff of the contents with colored lines
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str, color: bool) -> String {
+    let diff = TextDiff::from_lines(old, new).unified_diff().context_radius(3).header(old_name, new_name).to_string();
+    if !color {
+        return diff;
+    }
+
+    let lines: Vec<String> = diff.lines().map(|line| {
+        let style = if line.starts_with("---") || line.starts_with("+++") {
+            Style::new().bold()
+        } else if line.starts_with("@@") {
+            Cyan.normal()
+        } else if line.starts_with('+') {
+            Green.normal()
+        } else if line.starts_with('-') {
+            Red.normal()
+        } else {
+            return line.to_string();
+        };
+        style.paint(line).to_string()
+    }).collect();
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::new_page;
+
+    #[test]
+    fn prune_revisions() {
+        let now = Utc::now();
+        let days_ago = |days| now - Duration::days(days);
+        let revisions = [(1, days_ago(40)), (2, days_ago(35)), (3, days_ago(10)), (4, days_ago(1))];
+
+        assert_eq!(expired(&revisions, &Retention { max_count: 2, max_days: None }, now), vec![1, 2]);
+        assert_eq!(expired(&revisions, &Retention { max_count: 50, max_days: Some(30) }, now), vec![1, 2]);
+        assert_eq!(expired(&revisions, &Retention { max_count: 50, max_days: Some(0) }, now), vec![1, 2, 3]);
+
+        assert_eq!(parse_range("2..5").unwrap(), (2, Some(5)));
+        assert_eq!(parse_range("2..").unwrap(), (2, None));
+        assert!(parse_range("..5").is_err());
+    }
+
+    #[test]
+    fn choose_diff_source() {
+        use self::DiffSource::*;
+
+        assert_eq!(diff_source("a", Some("2..3"), &[2, 3], true).unwrap(), Revisions(2, Some(3)));
+        assert_eq!(diff_source("a", Some("2"), &[2, 3], false).unwrap(), Revisions(2, None));
+        assert_eq!(diff_source("a", None, &[2, 3], true).unwrap(), Revisions(3, None));
+        assert_eq!(diff_source("a", None, &[], true).unwrap(), Git(None));
+        assert!(diff_source("a", None, &[], false).is_err());
+
+        // Git commits
+        assert_eq!(diff_source("a", Some("1a0868d"), &[1], true).unwrap(), Git(Some("1a0868d")));
+        assert_eq!(diff_source("a", Some("1a0868d..HEAD"), &[1], true).unwrap(), Git(Some("1a0868d..HEAD")));
+        assert_eq!(diff_source("a", Some("1234567"), &[1], true).unwrap(), Git(Some("1234567")));
+        assert_eq!(diff_source("a", Some("HEAD~3..HEAD~1"), &[], true).unwrap(), Git(Some("HEAD~3..HEAD~1")));
+        assert!(diff_source("a", Some("1a0868d"), &[1], false).is_err());
+    }
+
+    #[test]
+    fn save_only_changed_contents() {
+        use store::MemoryStore;
+
+        let mut store = MemoryStore::new();
+        let mut page = new_page("a");
+        store.insert(page.clone()).unwrap();
+
+        let previous = page.clone();
+        page.header.updated.push(Utc::now());
+        assert_eq!(save_changes(&store, &previous, &page, &Retention::default()).unwrap().len(), 1);
+
+        page.text = "edited".to_string();
+        store.apply(save_changes(&store, &previous, &page, &Retention::default()).unwrap()).unwrap();
+        assert_eq!(store.revisions("a").unwrap(), vec![1]);
+        assert_eq!(store.get_revision("a", 1).unwrap().text, "");
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{DateTime, Days, Local, NaiveDate, SecondsFormat, Utc};
+use rusqlite::{self, params, params_from_iter, Connection, OptionalExtension, Row};
+use serde_json;
+
+use date::{page_date, DateRange, DIARY_ID_FORMAT};
+use error::Error;
+use page::{Page, PageHeader};
+use store::{Change, Store};
+
+// File name of the database in the app directory
+pub const DATABASE_FILENAME: &str = "diary.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS pages (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    insert_title INTEGER NOT NULL,
+    created TEXT NOT NULL,
+    updated TEXT NOT NULL,
+    memo INTEGER NOT NULL,
+    prev TEXT NOT NULL,
+    next TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS pages_created ON pages (created);
+CREATE TABLE IF NOT EXISTS texts (
+    id TEXT PRIMARY KEY,
+    text TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS trash (
+    id TEXT NOT NULL,
+    removed TEXT NOT NULL,
+    contents TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+// Changes of the schema applied in order. `PRAGMA user_version` is the number of applied migrations.
+const MIGRATIONS: [&str; 3] = [
+    "ALTER TABLE pages ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';",
+    "CREATE TABLE revisions (id TEXT NOT NULL, number INTEGER NOT NULL, contents TEXT NOT NULL, PRIMARY KEY (id, number));",
+    // Revisions of removed pages are kept in the trash with their numbers
+    "ALTER TABLE trash ADD COLUMN number INTEGER;",
+];
+
+// Format of times in the database, sortable as text
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+fn parse_time(s: &str) -> Result<DateTime<Utc>, Error> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|err| Error::Corrupted(format!("Invalid time `{}` in the database: {}", s, err)))
+}
+
+// Columns read by `page_from_row`
+const PAGE_COLUMNS: &str = "pages.id, pages.title, pages.insert_title, pages.created, pages.updated, pages.memo, pages.tags, pages.prev, pages.next, texts.text
+    FROM pages LEFT JOIN texts ON texts.id = pages.id";
+
+// Row of `PAGE_COLUMNS` before parsing the JSON and times in it
+struct PageRow {
+    id: String,
+    title: String,
+    insert_title: bool,
+    created: String,
+    updated: String,
+    memo: bool,
+    tags: String,
+    prev: String,
+    next: String,
+    text: Option<String>,
+}
+
+fn page_row(row: &Row) -> rusqlite::Result<PageRow> {
+    Ok(PageRow {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        insert_title: row.get(2)?,
+        created: row.get(3)?,
+        updated: row.get(4)?,
+        memo: row.get(5)?,
+        tags: row.get(6)?,
+        prev: row.get(7)?,
+        next: row.get(8)?,
+        text: row.get(9)?,
+    })
+}
+
+fn page_from_row(row: PageRow) -> Result<Page, Error> {
+    let updated: Vec<String> = serde_json::from_str(&row.updated)
+        .map_err(|err| Error::Corrupted(format!("Invalid updated times of `{}` in the database: {}", row.id, err)))?;
+    let tags: Vec<String> = serde_json::from_str(&row.tags)
+        .map_err(|err| Error::Corrupted(format!("Invalid tags of `{}` in the database: {}", row.id, err)))?;
+
+    Ok(Page {
+        id: row.id,
+        header: PageHeader {
+            title: row.title,
+            insert_title: row.insert_title,
+            created: parse_time(&row.created)?,
+            updated: updated.iter().map(|time| parse_time(time)).collect::<Result<_, _>>()?,
+            memo: row.memo,
+            tags,
+            prev: row.prev,
+            next: row.next,
+        },
+        text: row.text.unwrap_or_default(),
+    })
+}
+
+fn get_page(conn: &Connection, id: &str) -> Result<Option<Page>, Error> {
+    let row = conn.query_row(&format!("SELECT {} WHERE pages.id = ?1", PAGE_COLUMNS), params![id], page_row)
+        .optional()
+        .map_err(|err| Error::database(format!("Unable to read page `{}`", id), err))?;
+
+    row.map(page_from_row).transpose()
+}
+
+fn put_page(conn: &Connection, page: &Page) -> Result<(), Error> {
+    let header = &page.header;
+    let updated: Vec<String> = header.updated.iter().map(format_time).collect();
+    let updated = serde_json::to_string(&updated).unwrap();
+    let tags = serde_json::to_string(&header.tags).unwrap();
+
+    conn.execute(
+        "INSERT OR REPLACE INTO pages (id, title, insert_title, created, updated, memo, tags, prev, next)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![page.id, header.title, header.insert_title, format_time(&header.created), updated, header.memo, tags, header.prev, header.next],
+    ).map_err(|err| Error::database(format!("Unable to write page `{}`", page.id), err))?;
+
+    conn.execute(
+        "INSERT OR REPLACE INTO texts (id, text) VALUES (?1, ?2)",
+        params![page.id, page.text],
+    ).map_err(|err| Error::database(format!("Unable to write page `{}`", page.id), err))?;
+
+    Ok(())
+}
+
+fn remove_page(conn: &Connection, id: &str) -> Result<(), Error> {
+    let count = conn.execute("DELETE FROM pages WHERE id = ?1", params![id])
+        .and_then(|count| conn.execute("DELETE FROM texts WHERE id = ?1", params![id]).map(|_| count))
+        .and_then(|count| conn.execute("DELETE FROM revisions WHERE id = ?1", params![id]).map(|_| count))
+        .map_err(|err| Error::database(format!("Unable to remove page `{}`", id), err))?;
+
+    if count == 0 {
+        return Err(Error::NotFound(format!("`{}` does not exists.", id)));
+    }
+
+    Ok(())
+}
+
+// Store saving pages to the SQLite database `diary.db`
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(directory: &str) -> Result<SqliteStore, Error> {
+        let path = Path::new(directory).join(DATABASE_FILENAME);
+        let conn = Connection::open(&path)
+            .map_err(|err| Error::database(format!("Unable to open database `{}`", path.to_string_lossy()), err))?;
+
+        SqliteStore::from_connection(conn)
+    }
+
+    pub fn open_in_memory() -> Result<SqliteStore, Error> {
+        let conn = Connection::open_in_memory()
+            .map_err(|err| Error::database("Unable to open database in memory".to_string(), err))?;
+
+        SqliteStore::from_connection(conn)
+    }
+
+    fn from_connection(conn: Connection) -> Result<SqliteStore, Error> {
+        conn.execute_batch(SCHEMA)
+            .map_err(|err| Error::database("Unable to create tables".to_string(), err))?;
+
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|err| Error::database("Unable to read the schema version".to_string(), err))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(&format!("BEGIN; {} PRAGMA user_version = {}; COMMIT;", migration, i + 1))
+                .map_err(|err| Error::database("Unable to update tables".to_string(), err))?;
+        }
+
+        Ok(SqliteStore { conn })
+    }
+}
+
+impl Store for SqliteStore {
+    fn get(&self, id: &str) -> Result<Page, Error> {
+        get_page(&self.conn, id)?.ok_or_else(|| Error::NotFound(format!("`{}` does not exists.", id)))
+    }
+
+    fn exists(&self, id: &str) -> Result<bool, Error> {
+        self.conn.query_row("SELECT 1 FROM pages WHERE id = ?1", params![id], |_| Ok(()))
+            .optional()
+            .map(|row| row.is_some())
+            .map_err(|err| Error::database(format!("Unable to read page `{}`", id), err))
+    }
+
+    fn ids(&self) -> Result<Vec<String>, Error> {
+        let mut statement = self.conn.prepare("SELECT id FROM pages")
+            .map_err(|err| Error::database("Unable to list pages".to_string(), err))?;
+        let ids = statement.query_map([], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(|err| Error::database("Unable to list pages".to_string(), err))?;
+
+        Ok(ids)
+    }
+
+    fn head(&self) -> Result<String, Error> {
+        let head = self.conn.query_row("SELECT value FROM meta WHERE key = 'head'", [], |row| row.get(0))
+            .optional()
+            .map_err(|err| Error::database("Unable to read head".to_string(), err))?;
+
+        Ok(head.unwrap_or_else(|| "NULL".to_string()))
+    }
+
+    fn apply(&mut self, changes: Vec<Change>) -> Result<(), Error> {
+        // Rolled back when dropped on error
+        let tx = self.conn.transaction()
+            .map_err(|err| Error::database("Unable to begin transaction".to_string(), err))?;
+
+        for change in changes {
+            match change {
+                Change::Put(page) => put_page(&tx, &page)?,
+                Change::Remove(id) => remove_page(&tx, &id)?,
+                Change::Trash(id) => {
+                    let page = get_page(&tx, &id)?.ok_or_else(|| Error::NotFound(format!("`{}` does not exists.", id)))?;
+                    let removed = Local::now().to_rfc3339();
+                    tx.execute(
+                        "INSERT INTO trash (id, removed, contents) VALUES (?1, ?2, ?3)",
+                        params![id, removed, page.to_str()?],
+                    ).and_then(|_| tx.execute(
+                        "INSERT INTO trash (id, removed, contents, number) SELECT id, ?2, contents, number FROM revisions WHERE id = ?1",
+                        params![id, removed],
+                    )).map_err(|err| Error::database(format!("Unable to move page `{}` into trash", id), err))?;
+                    remove_page(&tx, &id)?;
+                },
+                Change::Rename(prev_id, next_id) => {
+                    let count = tx.execute("UPDATE pages SET id = ?2 WHERE id = ?1", params![prev_id, next_id])
+                        .and_then(|count| tx.execute("UPDATE texts SET id = ?2 WHERE id = ?1", params![prev_id, next_id]).map(|_| count))
+                        .and_then(|count| tx.execute("UPDATE revisions SET id = ?2 WHERE id = ?1", params![prev_id, next_id]).map(|_| count))
+                        .map_err(|err| Error::database(format!("Unable to rename page `{}`", prev_id), err))?;
+                    if count == 0 {
+                        return Err(Error::NotFound(format!("`{}` does not exists.", prev_id)));
+                    }
+                },
+                Change::SetHead(id) => {
+                    tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES ('head', ?1)", params![id])
+                        .map_err(|err| Error::database("Unable to write head".to_string(), err))?;
+                },
+                Change::PutRevision(number, page) => {
+                    tx.execute("INSERT OR REPLACE INTO revisions (id, number, contents) VALUES (?1, ?2, ?3)", params![page.id, number, page.to_str()?])
+                        .map_err(|err| Error::database(format!("Unable to write revision {} of `{}`", number, page.id), err))?;
+                },
+                Change::RemoveRevision(id, number) => {
+                    tx.execute("DELETE FROM revisions WHERE id = ?1 AND number = ?2", params![id, number])
+                        .map_err(|err| Error::database(format!("Unable to remove revision {} of `{}`", number, id), err))?;
+                },
+            }
+        }
+
+        tx.commit().map_err(|err| Error::database("Unable to commit transaction".to_string(), err))
+    }
+
+    fn revisions(&self, id: &str) -> Result<Vec<u32>, Error> {
+        let mut statement = self.conn.prepare("SELECT number FROM revisions WHERE id = ?1 ORDER BY number")
+            .map_err(|err| Error::database(format!("Unable to list revisions of `{}`", id), err))?;
+        let numbers = statement.query_map(params![id], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(|err| Error::database(format!("Unable to list revisions of `{}`", id), err))?;
+
+        Ok(numbers)
+    }
+
+    fn get_revision(&self, id: &str, number: u32) -> Result<Page, Error> {
+        let contents: Option<String> = self.conn.query_row("SELECT contents FROM revisions WHERE id = ?1 AND number = ?2", params![id, number], |row| row.get(0))
+            .optional()
+            .map_err(|err| Error::database(format!("Unable to read revision {} of `{}`", number, id), err))?;
+
+        match contents {
+            Some(contents) => Ok(Page::from_str(&contents, id)?),
+            None => Err(Error::NotFound(format!("Revision {} of `{}` does not exists.", number, id))),
+        }
+    }
+
+    fn find(&self, range: &DateRange, tags: &[&str]) -> Result<Vec<Page>, Error> {
+        let format = |date: NaiveDate| date.format(DIARY_ID_FORMAT).to_string();
+
+        // Narrow down pages by the columns, then check them exactly as the date of a page which is not
+        // a diary depends on the local time zone and inline tags are in the text
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if !range.is_unbounded() {
+            let mut id_conditions = Vec::new();
+            let mut created_conditions = Vec::new();
+            let mut id_values = Vec::new();
+            let mut created_values = Vec::new();
+            if let Some(since) = range.since {
+                id_conditions.push("pages.id >= ?");
+                id_values.push(format(since));
+                created_conditions.push("pages.created >= ?");
+                created_values.push(format(since.checked_sub_days(Days::new(1)).unwrap_or(since)));
+            }
+            if let Some(until) = range.until {
+                id_conditions.push("pages.id <= ?");
+                id_values.push(format(until));
+                if let Some(limit) = until.checked_add_days(Days::new(2)) {
+                    created_conditions.push("pages.created < ?");
+                    created_values.push(format(limit));
+                }
+            }
+            if created_conditions.is_empty() {
+                created_conditions.push("1");
+            }
+            conditions.push(format!("(({}) OR ({}))", id_conditions.join(" AND "), created_conditions.join(" AND ")));
+            values.extend(id_values);
+            values.extend(created_values);
+        }
+        for tag in tags {
+            conditions.push("(instr(pages.tags, ?) > 0 OR instr(texts.text, ?) > 0)".to_string());
+            values.push(serde_json::to_string(tag).unwrap());
+            values.push(format!("#{}", tag));
+        }
+
+        let sql = if conditions.is_empty() {
+            format!("SELECT {}", PAGE_COLUMNS)
+        } else {
+            format!("SELECT {} WHERE {}", PAGE_COLUMNS, conditions.join(" AND "))
+        };
+        let mut statement = self.conn.prepare(&sql)
+            .map_err(|err| Error::database("Unable to find pages".to_string(), err))?;
+        let rows: Vec<PageRow> = statement.query_map(params_from_iter(values), page_row)
+            .and_then(|rows| rows.collect())
+            .map_err(|err| Error::database("Unable to find pages".to_string(), err))?;
+
+        let mut pages = Vec::new();
+        for row in rows {
+            let page = page_from_row(row)?;
+            if range.contains(page_date(&page)) && page.has_tags(tags) {
+                pages.push(page);
+            }
+        }
+
+        // Order by the chain from the head, read at once instead of a query per page
+        let mut statement = self.conn.prepare("SELECT id, prev FROM pages")
+            .map_err(|err| Error::database("Unable to list pages".to_string(), err))?;
+        let links: HashMap<String, String> = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .and_then(|rows| rows.collect())
+            .map_err(|err| Error::database("Unable to list pages".to_string(), err))?;
+
+        let mut positions = HashMap::new();
+        let mut next_id = self.head()?;
+        while let Some(prev_id) = links.get(&next_id) {
+            // Stop at a cycle
+            if positions.insert(next_id.clone(), positions.len()).is_some() {
+                break;
+            }
+            next_id = prev_id.clone();
+        }
+
+        pages.retain(|page| positions.contains_key(&page.id));
+        pages.sort_by_key(|page| positions[&page.id]);
+
+        Ok(pages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use date::parse_date;
+    use store::MemoryStore;
+    use utils::new_page;
+
+    #[test]
+    fn round_trip_and_rollback() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let mut page = new_page("a");
+        page.text = "text".to_string();
+        store.insert(page.clone()).unwrap();
+        store.insert(new_page("b")).unwrap();
+
+        let loaded = store.get("a").unwrap();
+        assert_eq!(loaded.to_str().unwrap(), Page { header: PageHeader { next: "b".to_string(), ..page.header }, ..page }.to_str().unwrap());
+        assert_eq!(store.head().unwrap(), "b");
+
+        // The failed change leaves nothing changed
+        let result = store.apply(vec![Change::SetHead("a".to_string()), Change::Remove("none".to_string())]);
+        assert!(result.is_err());
+        assert_eq!(store.head().unwrap(), "b");
+
+        store.rename("a", "aa").unwrap();
+        store.apply(vec![Change::PutRevision(1, new_page("b"))]).unwrap();
+        store.delete("b", true).unwrap();
+        assert_eq!(store.head().unwrap(), "aa");
+        let trashed: Vec<Option<u32>> = store.conn.prepare("SELECT number FROM trash WHERE id = 'b' ORDER BY number").unwrap()
+            .query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(trashed, vec![None, Some(1)]);
+        assert_eq!(store.get("aa").unwrap().text, "text");
+    }
+
+    #[test]
+    fn find_like_memory_store() {
+        let date = |s| parse_date(s).unwrap();
+
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let mut memory = MemoryStore::new();
+        for (id, created, tags, text) in [
+            ("2018-04-30", "2018-05-01T01:00:00Z", vec![], "#home"),
+            ("2018-05-01", "2018-05-01T01:00:00Z", vec!["work".to_string()], "#homework"),
+            ("memo", "2018-05-01T12:00:00Z", vec![], "#home #work"),
+            ("other", "2018-06-01T12:00:00Z", vec!["home".to_string()], ""),
+        ] {
+            let mut page = new_page(id);
+            page.header.created = parse_time(created).unwrap();
+            page.header.tags = tags;
+            page.text = text.to_string();
+            store.insert(page.clone()).unwrap();
+            memory.insert(page).unwrap();
+        }
+
+        let ranges = [
+            DateRange::default(),
+            DateRange::new(Some(date("2018-05-01")), None),
+            DateRange::new(None, Some(date("2018-05-01"))),
+            DateRange::month("2018-05").unwrap(),
+        ];
+        let tags: [&[&str]; 4] = [&[], &["home"], &["work"], &["home", "work"]];
+        for range in &ranges {
+            for tags in &tags {
+                let ids = |pages: Vec<Page>| pages.into_iter().map(|page| page.id).collect::<Vec<_>>();
+                assert_eq!(ids(store.find(range, tags).unwrap()), ids(memory.find(range, tags).unwrap()), "{:?} {:?}", range, tags);
+            }
+        }
+        assert_eq!(store.find(&DateRange::default(), &["home"]).unwrap().len(), 3);
+    }
+}
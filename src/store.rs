@@ -0,0 +1,663 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use config::{Config, Storage};
+use crypto::{self, Cipher};
+use date::{page_date, DateRange};
+use error::Error;
+use journal::Journal;
+use page::Page;
+use sqlite::SqliteStore;
+use utils::{is_valid_id, read_file, PAGES_DIR, PAGE_EXTENSION, HEAD_FILENAME, REVISIONS_DIR, TRASH_DIR};
+
+// A change of pages. Changes passed to `Store::apply` together are applied all or nothing.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Put(Page),
+    Remove(String),
+    // Remove the page keeping it recoverable
+    Trash(String),
+    Rename(String, String),
+    SetHead(String),
+    // Save a previous version of the page as the revision of the number
+    PutRevision(u32, Page),
+    RemoveRevision(String, u32),
+}
+
+// Storage of pages linked from the head page by `prev`
+pub trait Store {
+    fn get(&self, id: &str) -> Result<Page, Error>;
+
+    fn exists(&self, id: &str) -> Result<bool, Error>;
+
+    // Ids of all pages including pages not linked from the head
+    fn ids(&self) -> Result<Vec<String>, Error>;
+
+    // Id of the newest page, or `NULL` if there are no pages
+    fn head(&self) -> Result<String, Error>;
+
+    fn apply(&mut self, changes: Vec<Change>) -> Result<(), Error>;
+
+    // Numbers of saved revisions of the page in ascending order.
+    // Revisions are removed, trashed and renamed with the page.
+    fn revisions(&self, id: &str) -> Result<Vec<u32>, Error>;
+
+    fn get_revision(&self, id: &str, number: u32) -> Result<Page, Error>;
+
+    // Pages in the range of dates having all of the tags, from the head to the oldest page
+    fn find(&self, range: &DateRange, tags: &[&str]) -> Result<Vec<Page>, Error> {
+        let mut pages = Vec::new();
+        let mut next_id = self.head()?;
+        while next_id != "NULL" {
+            let page = self.get(&next_id)?;
+            next_id = page.header.prev.clone();
+            if range.contains(page_date(&page)) && page.has_tags(tags) {
+                pages.push(page);
+            }
+        }
+
+        Ok(pages)
+    }
+
+    // Save the page without changing links of other pages
+    fn put(&mut self, page: &Page) -> Result<(), Error> {
+        self.apply(vec![Change::Put(page.clone())])
+    }
+
+    // Add the page to the head of the chain
+    fn insert(&mut self, page: Page) -> Result<(), Error> {
+        is_valid_id(&page.id)?;
+        if self.exists(&page.id)? {
+            return Err(Error::AlreadyExists(format!("`{}` already exists. use `diary edit {}`", page.id, page.id)));
+        }
+
+        let mut page = page;
+        let head_id = self.head()?;
+        page.header.prev = head_id.clone();
+        page.header.next = "NULL".to_string();
+
+        let mut changes = Vec::new();
+
+        // Update next of head page
+        if head_id != "NULL" {
+            let mut head_page = self.get(&head_id)?;
+            head_page.header.next = page.id.clone();
+            changes.push(Change::Put(head_page));
+        }
+
+        changes.push(Change::SetHead(page.id.clone()));
+        changes.push(Change::Put(page));
+
+        self.apply(changes)
+    }
+
+    // Remove the page, linking the previous page to the next page
+    fn delete(&mut self, id: &str, trash: bool) -> Result<(), Error> {
+        if !self.exists(id)? {
+            return Err(Error::NotFound(format!("`{}` does not exists.", id)));
+        }
+
+        let page = self.get(id)?;
+        let mut changes = Vec::new();
+
+        if page.header.prev != "NULL" {
+            let mut prev_page = self.get(&page.header.prev)?;
+            prev_page.header.next = page.header.next.clone();
+            changes.push(Change::Put(prev_page));
+        }
+
+        if page.header.next != "NULL" {
+            let mut next_page = self.get(&page.header.next)?;
+            next_page.header.prev = page.header.prev.clone();
+            changes.push(Change::Put(next_page));
+        } else {
+            changes.push(Change::SetHead(page.header.prev.clone()));
+        }
+
+        changes.push(if trash { Change::Trash(id.to_string()) } else { Change::Remove(id.to_string()) });
+
+        self.apply(changes)
+    }
+
+    // Change the id of the page, updating links to it
+    fn rename(&mut self, prev_id: &str, next_id: &str) -> Result<(), Error> {
+        is_valid_id(next_id)?;
+        if self.exists(next_id)? {
+            return Err(Error::AlreadyExists(format!("`{}` exists.", next_id)));
+        }
+
+        let page = self.get(prev_id)?;
+        let mut changes = vec![Change::Rename(prev_id.to_string(), next_id.to_string())];
+
+        if page.header.prev != "NULL" {
+            let mut prev_page = self.get(&page.header.prev)?;
+            prev_page.header.next = String::from(next_id);
+            changes.push(Change::Put(prev_page));
+        }
+
+        if page.header.next != "NULL" {
+            let mut next_page = self.get(&page.header.next)?;
+            next_page.header.prev = String::from(next_id);
+            changes.push(Change::Put(next_page));
+        } else {
+            changes.push(Change::SetHead(String::from(next_id)));
+        }
+
+        self.apply(changes)
+    }
+}
+
+impl<'s> dyn Store + 's {
+    // Iterate pages from the head to the oldest page
+    pub fn iter(&self) -> Result<Pages<'_>, Error> {
+        Ok(Pages {
+            store: self,
+            next_id: self.head()?,
+        })
+    }
+}
+
+pub struct Pages<'a> {
+    store: &'a (dyn Store + 'a),
+    next_id: String,
+}
+
+impl<'a> Iterator for Pages<'a> {
+    type Item = Result<Page, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_id == "NULL" {
+            return None;
+        }
+
+        let page = self.store.get(&self.next_id);
+        self.next_id = match page {
+            Ok(ref page) => page.header.prev.clone(),
+            // Stop after the error
+            Err(_) => "NULL".to_string(),
+        };
+
+        Some(page)
+    }
+}
+
+// Store saving each page to `pages/<id>.page` and the head id to `HEAD`.
+// With a cipher, page files are encrypted. HEAD is kept in plain text as ids are file names anyway.
+pub struct FsStore {
+    directory: PathBuf,
+    cipher: Option<Cipher>,
+}
+
+impl FsStore {
+    pub fn new(directory: &str) -> FsStore {
+        FsStore {
+            directory: PathBuf::from(directory),
+            cipher: None,
+        }
+    }
+
+    pub fn with_cipher(directory: &str, cipher: Cipher) -> FsStore {
+        FsStore {
+            directory: PathBuf::from(directory),
+            cipher: Some(cipher),
+        }
+    }
+
+    // Path of the page relative to the app directory
+    fn page_path(id: &str) -> PathBuf {
+        Path::new(PAGES_DIR).join(format!("{}.{}", id, PAGE_EXTENSION))
+    }
+
+    // Path of the revision relative to the app directory
+    fn revision_path(id: &str, number: u32) -> PathBuf {
+        Path::new(REVISIONS_DIR).join(id).join(format!("{}.{}", number, PAGE_EXTENSION))
+    }
+
+    // Read the page file at `file` relative to the app directory
+    fn read_page(&self, file: &Path, id: &str) -> Result<Page, Error> {
+        let filepath = self.directory.join(file);
+        let mut contents = String::new();
+        fs::File::open(&filepath)
+            .map_err(|err| Error::io(format!("Unable to open page file `{}`", filepath.to_string_lossy()), err))?
+            .read_to_string(&mut contents)
+            .map_err(|err| Error::io(format!("Unable to read page file `{}`", filepath.to_string_lossy()), err))?;
+
+        if let Some(cipher) = &self.cipher {
+            contents = cipher.decrypt(&contents, file)?;
+        } else if crypto::is_encrypted(&contents) {
+            return Err(Error::Encryption(format!("`{}` is encrypted", filepath.to_string_lossy())));
+        }
+
+        // Parse page file contents
+        let page = Page::from_str(&contents, id)?;
+
+        Ok(page)
+    }
+
+    // Contents of the page file at `file` relative to the app directory
+    fn page_contents(&self, page: &Page, file: &Path) -> Result<String, Error> {
+        let contents = page.to_str()?;
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(&contents, file),
+            None => Ok(contents),
+        }
+    }
+
+    // Move the file in the journal. Encrypted files are encrypted again as they are bound to their paths.
+    fn move_file(&self, journal: &mut Journal, from: PathBuf, to: PathBuf) -> Result<(), Error> {
+        let cipher = match &self.cipher {
+            Some(cipher) => cipher,
+            None => {
+                journal.rename(from, to);
+                return Ok(());
+            },
+        };
+
+        let path = self.directory.join(&from);
+        let contents = read_file(&path)
+            .map_err(|err| Error::io(format!("Unable to read page file `{}`", path.to_string_lossy()), err))?;
+        journal.write(to.clone(), cipher.encrypt(&cipher.decrypt(&contents, &from)?, &to)?);
+        journal.remove(from);
+
+        Ok(())
+    }
+
+    // Name in the trash directory not used yet. The page is moved to `trash/<name>.page` and its
+    // revisions to `trash/revisions/<name>/`, so that both can be moved back.
+    fn trash_name(&self, id: &str) -> Result<String, Error> {
+        let trash_dir = self.directory.join(TRASH_DIR);
+        if !trash_dir.exists() {
+            fs::create_dir(&trash_dir)
+                .map_err(|err| Error::io(format!("Unable to create directory `{}`", trash_dir.to_string_lossy()), err))?;
+        }
+
+        // Keep the page removed before if the same id is already in trash
+        if trash_dir.join(format!("{}.{}", id, PAGE_EXTENSION)).exists() {
+            return Ok(format!("{}.{}", id, Local::now().format("%Y%m%d%H%M%S")));
+        }
+
+        Ok(id.to_string())
+    }
+}
+
+impl Store for FsStore {
+    fn get(&self, id: &str) -> Result<Page, Error> {
+        let file = FsStore::page_path(id);
+        if !self.directory.join(&file).exists() {
+            return Err(Error::NotFound(format!("`{}` does not exists.", id)));
+        }
+
+        self.read_page(&file, id)
+    }
+
+    fn exists(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.directory.join(FsStore::page_path(id)).exists())
+    }
+
+    fn ids(&self) -> Result<Vec<String>, Error> {
+        let pages_dir = self.directory.join(PAGES_DIR);
+        let entries = fs::read_dir(&pages_dir)
+            .map_err(|err| Error::io(format!("Unable to list files in directory `{}`", pages_dir.to_string_lossy()), err))?;
+
+        let mut ids = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(|err| Error::io(format!("Unable to list files in directory `{}`", pages_dir.to_string_lossy()), err))?
+                .path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some(PAGE_EXTENSION) {
+                continue;
+            }
+
+            if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                ids.push(id.to_string());
+            }
+        }
+
+        Ok(ids)
+    }
+
+    fn head(&self) -> Result<String, Error> {
+        // Get filepath to save head page id
+        let head_filepath = self.directory.join(HEAD_FILENAME);
+
+        // Get head page id
+        let mut file = fs::File::open(&head_filepath)
+            .map_err(|err| Error::io(format!("Unable to open HEAD file `{}`", head_filepath.to_string_lossy()), err))?;
+
+        let mut head_id = String::new();
+        file.read_to_string(&mut head_id)
+            .map_err(|err| Error::io(format!("Unable to read HEAD file `{}`", head_filepath.to_string_lossy()), err))?;
+
+        Ok(head_id)
+    }
+
+    fn apply(&mut self, changes: Vec<Change>) -> Result<(), Error> {
+        let mut journal = Journal::new(&self.directory.to_string_lossy());
+        // Ids whose revisions are moved or removed
+        let mut moved = Vec::new();
+
+        for change in changes {
+            match change {
+                Change::Put(page) => {
+                    let file = FsStore::page_path(&page.id);
+                    let contents = self.page_contents(&page, &file)?;
+                    journal.write(file, contents);
+                },
+                Change::Remove(id) => {
+                    journal.remove(FsStore::page_path(&id));
+                    for number in self.revisions(&id)? {
+                        journal.remove(FsStore::revision_path(&id, number));
+                    }
+                    moved.push(id);
+                },
+                Change::Trash(id) => {
+                    let name = self.trash_name(&id)?;
+                    let trash_dir = Path::new(TRASH_DIR);
+                    self.move_file(&mut journal, FsStore::page_path(&id), trash_dir.join(format!("{}.{}", name, PAGE_EXTENSION)))?;
+                    for number in self.revisions(&id)? {
+                        self.move_file(&mut journal, FsStore::revision_path(&id, number), trash_dir.join(FsStore::revision_path(&name, number)))?;
+                    }
+                    moved.push(id);
+                },
+                Change::Rename(prev_id, next_id) => {
+                    self.move_file(&mut journal, FsStore::page_path(&prev_id), FsStore::page_path(&next_id))?;
+                    for number in self.revisions(&prev_id)? {
+                        self.move_file(&mut journal, FsStore::revision_path(&prev_id, number), FsStore::revision_path(&next_id, number))?;
+                    }
+                    moved.push(prev_id);
+                },
+                Change::SetHead(id) => journal.write_head(&id),
+                Change::PutRevision(number, page) => {
+                    let file = FsStore::revision_path(&page.id, number);
+                    let contents = self.page_contents(&page, &file)?;
+                    journal.write(file, contents);
+                },
+                Change::RemoveRevision(id, number) => journal.remove(FsStore::revision_path(&id, number)),
+            }
+        }
+
+        journal.commit()?;
+
+        // Remove directories left empty, which fails if they are not empty
+        for id in moved {
+            let _ = fs::remove_dir(self.directory.join(REVISIONS_DIR).join(id));
+        }
+
+        Ok(())
+    }
+
+    fn revisions(&self, id: &str) -> Result<Vec<u32>, Error> {
+        let dir = self.directory.join(REVISIONS_DIR).join(id);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let entries = fs::read_dir(&dir)
+            .map_err(|err| Error::io(format!("Unable to list files in directory `{}`", dir.to_string_lossy()), err))?;
+
+        let mut numbers: Vec<u32> = entries.flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some(PAGE_EXTENSION) {
+                    return None;
+                }
+                path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok())
+            })
+            .collect();
+        numbers.sort();
+
+        Ok(numbers)
+    }
+
+    fn get_revision(&self, id: &str, number: u32) -> Result<Page, Error> {
+        let file = FsStore::revision_path(id, number);
+        if !self.directory.join(&file).exists() {
+            return Err(Error::NotFound(format!("Revision {} of `{}` does not exists.", number, id)));
+        }
+
+        self.read_page(&file, id)
+    }
+}
+
+// Store keeping pages in memory
+#[derive(Debug)]
+pub struct MemoryStore {
+    pages: HashMap<String, Page>,
+    head: String,
+    // Removed pages with their revisions
+    pub trash: Vec<(Page, BTreeMap<u32, Page>)>,
+    revisions: HashMap<String, BTreeMap<u32, Page>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            pages: HashMap::new(),
+            head: "NULL".to_string(),
+            trash: Vec::new(),
+            revisions: HashMap::new(),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl Store for MemoryStore {
+    fn get(&self, id: &str) -> Result<Page, Error> {
+        self.pages.get(id).cloned().ok_or_else(|| Error::NotFound(format!("`{}` does not exists.", id)))
+    }
+
+    fn exists(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.pages.contains_key(id))
+    }
+
+    fn ids(&self) -> Result<Vec<String>, Error> {
+        Ok(self.pages.keys().cloned().collect())
+    }
+
+    fn head(&self) -> Result<String, Error> {
+        Ok(self.head.clone())
+    }
+
+    fn apply(&mut self, changes: Vec<Change>) -> Result<(), Error> {
+        // Apply to copies so that nothing is changed on error
+        let mut pages = self.pages.clone();
+        let mut head = self.head.clone();
+        let mut trash = self.trash.clone();
+        let mut revisions = self.revisions.clone();
+
+        for change in changes {
+            match change {
+                Change::Put(page) => { pages.insert(page.id.clone(), page); },
+                Change::Remove(id) => {
+                    pages.remove(&id).ok_or_else(|| Error::NotFound(format!("`{}` does not exists.", id)))?;
+                    revisions.remove(&id);
+                },
+                Change::Trash(id) => {
+                    let page = pages.remove(&id).ok_or_else(|| Error::NotFound(format!("`{}` does not exists.", id)))?;
+                    trash.push((page, revisions.remove(&id).unwrap_or_default()));
+                },
+                Change::Rename(prev_id, next_id) => {
+                    let mut page = pages.remove(&prev_id).ok_or_else(|| Error::NotFound(format!("`{}` does not exists.", prev_id)))?;
+                    page.id = next_id.clone();
+                    pages.insert(next_id.clone(), page);
+                    if let Some(page_revisions) = revisions.remove(&prev_id) {
+                        revisions.insert(next_id, page_revisions);
+                    }
+                },
+                Change::SetHead(id) => head = id,
+                Change::PutRevision(number, page) => { revisions.entry(page.id.clone()).or_default().insert(number, page); },
+                Change::RemoveRevision(id, number) => {
+                    revisions.get_mut(&id).and_then(|page_revisions| page_revisions.remove(&number));
+                },
+            }
+        }
+
+        self.pages = pages;
+        self.head = head;
+        self.trash = trash;
+        self.revisions = revisions;
+
+        Ok(())
+    }
+
+    fn revisions(&self, id: &str) -> Result<Vec<u32>, Error> {
+        Ok(self.revisions.get(id).map(|page_revisions| page_revisions.keys().cloned().collect()).unwrap_or_default())
+    }
+
+    fn get_revision(&self, id: &str, number: u32) -> Result<Page, Error> {
+        self.revisions.get(id).and_then(|page_revisions| page_revisions.get(&number)).cloned()
+            .ok_or_else(|| Error::NotFound(format!("Revision {} of `{}` does not exists.", number, id)))
+    }
+}
+
+// Open the store selected in the config
+pub fn open(directory: &str, config: &Config) -> Result<Box<dyn Store>, Error> {
+    let encrypted = crypto::is_enabled(directory);
+    Ok(match config.storage {
+        Storage::Files if encrypted => Box::new(FsStore::with_cipher(directory, crypto::unlock(directory)?)),
+        Storage::Files => Box::new(FsStore::new(directory)),
+        Storage::Sqlite if encrypted => return Err(Error::Encryption("Encryption is not supported with `storage = \"sqlite\"`".to_string())),
+        Storage::Sqlite => Box::new(SqliteStore::open(directory)?),
+    })
+}
+
+// Replace all pages in `to` with pages in `from`. Returns the number of copied pages.
+// Refuses to remove all pages in `to` when `from` has no pages, which is likely a wrong source.
+pub fn copy(from: &dyn Store, to: &mut dyn Store) -> Result<usize, Error> {
+    let ids = from.ids()?;
+    if ids.is_empty() && !to.ids()?.is_empty() {
+        return Err(Error::NotFound(String::from("There are no pages to copy. Refusing to remove all pages")));
+    }
+
+    let mut changes = Vec::new();
+    for id in to.ids()? {
+        if !ids.contains(&id) {
+            changes.push(Change::Remove(id));
+        }
+    }
+
+    for id in &ids {
+        changes.push(Change::Put(from.get(id)?));
+        for number in from.revisions(id)? {
+            changes.push(Change::PutRevision(number, from.get_revision(id, number)?));
+        }
+    }
+    changes.push(Change::SetHead(from.head()?));
+
+    to.apply(changes)?;
+
+    Ok(ids.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use date::parse_date;
+    use std::env;
+    use std::process;
+    use utils::{new_page, write_file};
+
+    fn ids_from_head(store: &dyn Store) -> Vec<String> {
+        store.iter().unwrap().map(|page| page.unwrap().id).collect()
+    }
+
+    #[test]
+    fn chain_operations() {
+        let mut store = MemoryStore::new();
+        for id in &["a", "b", "c"] {
+            store.insert(new_page(id)).unwrap();
+        }
+        assert_eq!(ids_from_head(&store), vec!["c", "b", "a"]);
+        assert!(store.insert(new_page("a")).is_err());
+
+        store.rename("b", "bb").unwrap();
+        assert_eq!(ids_from_head(&store), vec!["c", "bb", "a"]);
+        assert_eq!(store.get("a").unwrap().header.next, "bb");
+
+        store.delete("c", true).unwrap();
+        assert_eq!(store.head().unwrap(), "bb");
+        assert_eq!(store.get("bb").unwrap().header.next, "NULL");
+        assert_eq!(store.trash[0].0.id, "c");
+
+        store.delete("a", false).unwrap();
+        assert_eq!(ids_from_head(&store), vec!["bb"]);
+        assert_eq!(store.get("bb").unwrap().header.prev, "NULL");
+    }
+
+    #[test]
+    fn remove_files() {
+        let directory = env::temp_dir().join(format!("diary-store-test-{}", process::id()));
+        fs::create_dir_all(directory.join(PAGES_DIR)).unwrap();
+        write_file(&directory.join(HEAD_FILENAME), "NULL").unwrap();
+
+        let mut store = FsStore::new(directory.to_str().unwrap());
+        for id in &["a", "b", "c"] {
+            store.insert(new_page(id)).unwrap();
+        }
+        store.apply(vec![Change::PutRevision(1, new_page("b")), Change::PutRevision(1, new_page("c"))]).unwrap();
+
+        // `rm --trash` moves the page and its revisions into the trash
+        store.delete("b", true).unwrap();
+        assert_eq!(ids_from_head(&store), vec!["c", "a"]);
+        assert_eq!(store.get("a").unwrap().header.next, "c");
+        assert!(directory.join(TRASH_DIR).join("b.page").exists());
+        assert!(directory.join(TRASH_DIR).join(REVISIONS_DIR).join("b").join("1.page").exists());
+        assert!(!directory.join(REVISIONS_DIR).join("b").exists());
+
+        // A page of the same id is trashed under another name
+        store.insert(new_page("b")).unwrap();
+        store.delete("b", true).unwrap();
+        assert_eq!(fs::read_dir(directory.join(TRASH_DIR)).unwrap().count(), 3);
+
+        // `rm` removes the page and its revisions
+        store.delete("c", false).unwrap();
+        assert_eq!(ids_from_head(&store), vec!["a"]);
+        assert!(!directory.join(PAGES_DIR).join("c.page").exists());
+        assert!(!directory.join(REVISIONS_DIR).join("c").exists());
+        assert!(store.delete("c", false).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn find_pages() {
+        let date = |s| parse_date(s).unwrap();
+
+        let mut store = MemoryStore::new();
+        for &(id, text) in &[("2018-05-01", "#work"), ("memo", "#work #home"), ("2018-05-03", "")] {
+            let mut page = new_page(id);
+            page.header.created = date("2018-05-02").and_hms_opt(12, 0, 0).unwrap().and_local_timezone(Local).unwrap().with_timezone(&Utc);
+            page.text = text.to_string();
+            store.insert(page).unwrap();
+        }
+
+        let ids = |pages: Vec<Page>| pages.into_iter().map(|page| page.id).collect::<Vec<_>>();
+        assert_eq!(ids(store.find(&DateRange::default(), &[]).unwrap()), vec!["2018-05-03", "memo", "2018-05-01"]);
+        assert_eq!(ids(store.find(&DateRange::new(Some(date("2018-05-02")), None), &[]).unwrap()), vec!["2018-05-03", "memo"]);
+        assert_eq!(ids(store.find(&DateRange::default(), &["work"]).unwrap()), vec!["memo", "2018-05-01"]);
+        assert_eq!(ids(store.find(&DateRange::new(None, Some(date("2018-05-02"))), &["work", "home"]).unwrap()), vec!["memo"]);
+    }
+
+    #[test]
+    fn copy_refuses_empty_source() {
+        let mut to = MemoryStore::new();
+        to.insert(new_page("a")).unwrap();
+
+        assert!(copy(&MemoryStore::new(), &mut to).is_err());
+        assert_eq!(ids_from_head(&to), vec!["a"]);
+
+        let mut empty = MemoryStore::new();
+        assert_eq!(copy(&MemoryStore::new(), &mut empty).unwrap(), 0);
+    }
+}
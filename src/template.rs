@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDateTime};
+use toml;
+
+use config::Config;
+use error::Error;
+use page::Page;
+use store::Store;
+use utils::{read_file, TemporaryPage, TemporaryPageHeader};
+
+// Directory of templates in the app directory, `templates/<name>.md`
+pub const TEMPLATES_DIR: &str = "templates";
+pub const TEMPLATE_EXTENSION: &str = "md";
+
+// Optional header of templates between `---` lines
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TemplateHeader {
+    title: Option<String>,
+    insert_title: Option<bool>,
+    tags: Vec<String>,
+}
+
+// Values of placeholders
+pub struct Context<'a> {
+    pub id: &'a str,
+    pub now: NaiveDateTime,
+    // Page the new page is linked after
+    pub prev: Option<&'a Page>,
+}
+
+// Items of task lists not checked yet, like `- [ ] item`
+pub fn unfinished_items(text: &str) -> Vec<String> {
+    text.lines()
+        .filter(|line| {
+            let item = line.trim_start();
+            ["- [ ]", "* [ ]", "+ [ ]"].iter().any(|marker| item.starts_with(marker))
+        })
+        .map(|line| line.to_string())
+        .collect()
+}
+
+// Replace `{{date}}`, `{{time}}`, `{{weekday}}`, `{{id}}`, `{{prev_id}}`, `{{prev_title}}` and
+// `{{unfinished}}`. Unknown placeholders are left as they are.
+pub fn render(template: &str, context: &Context) -> String {
+    let prev_id = context.prev.map(|page| page.id.as_str()).unwrap_or("");
+    let prev_title = context.prev.map(|page| page.header.title.as_str()).unwrap_or("");
+    let unfinished = context.prev.map(|page| unfinished_items(&page.text).join("\n")).unwrap_or_default();
+
+    template
+        .replace("{{date}}", &context.now.format("%Y-%m-%d").to_string())
+        .replace("{{time}}", &context.now.format("%H:%M").to_string())
+        .replace("{{weekday}}", &context.now.format("%A").to_string())
+        .replace("{{id}}", context.id)
+        .replace("{{prev_id}}", prev_id)
+        .replace("{{prev_title}}", prev_title)
+        .replace("{{unfinished}}", &unfinished)
+}
+
+// Path of the template. The variant for the weekday like `diary.monday.md` is preferred.
+pub fn find(directory: &str, name: &str, now: &NaiveDateTime) -> Option<PathBuf> {
+    let dir = Path::new(directory).join(TEMPLATES_DIR);
+    let weekday = now.format("%A").to_string().to_lowercase();
+
+    [format!("{}.{}.{}", name, weekday, TEMPLATE_EXTENSION), format!("{}.{}", name, TEMPLATE_EXTENSION)]
+        .iter()
+        .map(|file| dir.join(file))
+        .find(|path| path.exists())
+}
+
+// Split the header from the template
+fn parse(contents: &str, path: &Path) -> Result<(TemplateHeader, String), Error> {
+    let rest = match contents.strip_prefix("---\n") {
+        Some(rest) => rest,
+        None => return Ok((TemplateHeader::default(), contents.to_string())),
+    };
+
+    let (header, text) = match rest.find("\n---\n") {
+        Some(pos) => (&rest[..pos], &rest[pos + 5..]),
+        None => return Err(Error::Parse(format!("The header of template `{}` is not closed with `---`", path.to_string_lossy()))),
+    };
+
+    let header = toml::from_str(header)
+        .map_err(|err| Error::Parse(format!("Invalid header of template `{}`: {}", path.to_string_lossy(), err)))?;
+
+    Ok((header, text.to_string()))
+}
+
+// Initial contents of a new page. `name` is the template given explicitly, which must exist.
+// Otherwise the default template of the page kind is used if it exists.
+pub fn initial_page(directory: &str, config: &Config, store: &dyn Store, id: &str, memo: bool, name: Option<&str>) -> Result<TemporaryPage, Error> {
+    let mut page = TemporaryPage {
+        header: TemporaryPageHeader {
+            title: id.to_string(),
+            insert_title: true,
+            memo,
+            tags: Vec::new(),
+        },
+        text: String::new(),
+    };
+
+    let now = Local::now().naive_local();
+    let path = match name {
+        Some(name) => Some(find(directory, name, &now)
+            .ok_or_else(|| Error::NotFound(format!("Template `{}` does not exist in `{}`", name, Path::new(directory).join(TEMPLATES_DIR).to_string_lossy())))?),
+        None => find(directory, if memo { &config.templates.memo } else { &config.templates.diary }, &now),
+    };
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(page),
+    };
+
+    let contents = read_file(&path)
+        .map_err(|err| Error::io(format!("Unable to read template `{}`", path.to_string_lossy()), err))?;
+    let (header, text) = parse(&contents, &path)?;
+
+    let head_id = store.head()?;
+    let prev = if head_id != "NULL" { Some(store.get(&head_id)?) } else { None };
+    let context = Context { id, now, prev: prev.as_ref() };
+
+    if let Some(title) = header.title {
+        page.header.title = render(&title, &context);
+    }
+    if let Some(insert_title) = header.insert_title {
+        page.header.insert_title = insert_title;
+    }
+    page.header.tags = header.tags;
+    page.text = render(&text, &context);
+
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use utils::new_page;
+
+    #[test]
+    fn render_placeholders() {
+        let mut prev = new_page("2018-05-01");
+        prev.header.title = "Tuesday".to_string();
+        prev.text = "- [x] done\n- [ ] todo\n  * [ ] nested\ntext".to_string();
+
+        let context = Context {
+            id: "2018-05-02",
+            now: NaiveDate::from_ymd_opt(2018, 5, 2).unwrap().and_hms_opt(9, 30, 0).unwrap(),
+            prev: Some(&prev),
+        };
+
+        let template = "# {{weekday}} {{date}} {{time}}\nafter {{prev_title}} ({{prev_id}})\n{{unfinished}}\n{{unknown}}";
+        assert_eq!(render(template, &context), "# Wednesday 2018-05-02 09:30\nafter Tuesday (2018-05-01)\n- [ ] todo\n  * [ ] nested\n{{unknown}}");
+    }
+}
@@ -0,0 +1,617 @@
+use std::collections::HashSet;
+use std::io::{self, Stdout, Write};
+
+use chrono::{Local, NaiveDate};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, DisableLineWrap, EnableLineWrap, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use config::Config;
+use date::{page_date, parse_date, DateRange, DIARY_ID_FORMAT};
+use error::Error;
+use index::{self, Field, Index, Query};
+use markdown;
+use page::Page;
+use store::{self, Store};
+use template;
+use utils::{create_new_page, edit_page_by_id};
+
+// Maximum width of the page list
+const LIST_WIDTH: usize = 32;
+
+const HELP: &str = "j/k move  J/K scroll  e edit  n new  r rename  / search  d date  q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Normal,
+    Search,
+    Jump,
+    New,
+    Rename,
+}
+
+impl Mode {
+    fn prompt(self) -> &'static str {
+        match self {
+            Mode::Normal => "",
+            Mode::Search => "/",
+            Mode::Jump => "date (YYYY-MM-DD or YYYY-MM): ",
+            Mode::New => "new page id (empty for today): ",
+            Mode::Rename => "rename to: ",
+        }
+    }
+}
+
+fn io_error(err: io::Error) -> Error {
+    Error::io(String::from("Unable to draw the terminal"), err)
+}
+
+// Cut the string to the display width
+fn truncate(s: &str, width: usize) -> String {
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let c_width = c.width().unwrap_or(0);
+        if used + c_width > width {
+            break;
+        }
+        truncated.push(c);
+        used += c_width;
+    }
+
+    truncated
+}
+
+// Full-screen mode of the terminal, restored when dropped even on errors
+struct Screen {
+    stdout: Stdout,
+    active: bool,
+}
+
+impl Screen {
+    fn new() -> Screen {
+        Screen { stdout: io::stdout(), active: false }
+    }
+
+    // Long lines of the preview are cut at the edge instead of wrapped
+    fn enter(&mut self) -> Result<(), Error> {
+        terminal::enable_raw_mode().map_err(io_error)?;
+        self.active = true;
+        execute!(self.stdout, EnterAlternateScreen, Hide, DisableLineWrap, Clear(ClearType::All)).map_err(io_error)
+    }
+
+    fn leave(&mut self) -> Result<(), Error> {
+        if !self.active {
+            return Ok(());
+        }
+
+        self.active = false;
+        execute!(self.stdout, EnableLineWrap, Show, LeaveAlternateScreen).map_err(io_error)?;
+        terminal::disable_raw_mode().map_err(io_error)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.leave();
+    }
+}
+
+struct App<'a> {
+    directory: &'a str,
+    config: &'a Config,
+    store: Box<dyn Store>,
+    index: Index,
+    // Pages from the head, newest first
+    pages: Vec<Page>,
+    // Indexes of pages in the list, filtered by the search
+    shown: Vec<usize>,
+    // Index in `shown`
+    selected: usize,
+    list_top: usize,
+    preview_top: usize,
+    // Rows of the list and the preview when last drawn
+    height: usize,
+    mode: Mode,
+    input: String,
+    message: String,
+}
+
+impl<'a> App<'a> {
+    fn new(directory: &'a str, config: &'a Config) -> Result<App<'a>, Error> {
+        let store = store::open(directory, config)?;
+        App::with_store(directory, config, store)
+    }
+
+    fn with_store(directory: &'a str, config: &'a Config, store: Box<dyn Store>) -> Result<App<'a>, Error> {
+        let index = Index::open(directory, store.as_ref())?;
+        let mut app = App {
+            directory,
+            config,
+            store,
+            index,
+            pages: Vec::new(),
+            shown: Vec::new(),
+            selected: 0,
+            list_top: 0,
+            preview_top: 0,
+            height: 0,
+            mode: Mode::Normal,
+            input: String::new(),
+            message: String::from(HELP),
+        };
+        app.reload(None)?;
+
+        Ok(app)
+    }
+
+    // Load pages again, selecting the page of the id if it is given
+    fn reload(&mut self, select: Option<&str>) -> Result<(), Error> {
+        let selected_id = select.map(|id| id.to_string()).or_else(|| self.selected_page().map(|page| page.id.clone()));
+
+        self.pages = self.store.find(&DateRange::default(), &[])?;
+        self.index = Index::open(self.directory, self.store.as_ref())?;
+        self.shown = (0..self.pages.len()).collect();
+        self.selected = selected_id
+            .and_then(|id| self.shown.iter().position(|&i| self.pages[i].id == id))
+            .unwrap_or(0);
+        self.preview_top = 0;
+
+        Ok(())
+    }
+
+    fn selected_page(&self) -> Option<&Page> {
+        self.shown.get(self.selected).map(|&i| &self.pages[i])
+    }
+
+    fn select(&mut self, selected: usize) {
+        if self.shown.is_empty() {
+            return;
+        }
+
+        let selected = selected.min(self.shown.len() - 1);
+        if selected != self.selected {
+            self.selected = selected;
+            self.preview_top = 0;
+        }
+    }
+
+    // Filter the list by the query in the input
+    fn search(&mut self) {
+        if self.input.trim().is_empty() {
+            self.shown = (0..self.pages.len()).collect();
+            self.select(0);
+            return;
+        }
+
+        // Keep the last results while the query is incomplete
+        let query = match Query::parse(&self.input) {
+            Ok(query) => query,
+            Err(_) => return,
+        };
+
+        let matched: HashSet<String> = self.index.search(&query, Field::All).into_iter().map(|(id, _)| id).collect();
+        self.shown = (0..self.pages.len()).filter(|&i| matched.contains(&self.pages[i].id)).collect();
+        self.selected = 0;
+        self.preview_top = 0;
+        self.message = format!("{} pages match", self.shown.len());
+    }
+
+    // Select the newest page on or before the date
+    fn jump(&mut self, s: &str) -> Result<(), Error> {
+        let date: NaiveDate = match parse_date(s) {
+            Ok(date) => date,
+            Err(_) => DateRange::month(s)?.until.unwrap(),
+        };
+
+        let position = self.shown.iter().position(|&i| page_date(&self.pages[i]) <= date)
+            .ok_or_else(|| Error::NotFound(format!("No pages on or before {}", date)))?;
+        self.select(position);
+
+        Ok(())
+    }
+
+    // Run the closure with the terminal restored, for editors
+    fn suspend<F>(&mut self, screen: &mut Screen, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut App) -> Result<(), Error>
+    {
+        screen.leave()?;
+        let result = f(self);
+        screen.enter()?;
+
+        result
+    }
+
+    fn edit(&mut self, screen: &mut Screen) -> Result<(), Error> {
+        let id = match self.selected_page() {
+            Some(page) => page.id.clone(),
+            None => return Ok(()),
+        };
+
+        let (directory, config) = (self.directory, self.config);
+        self.suspend(screen, |app| edit_page_by_id(app.store.as_mut(), directory, &id, &config.editor, &config.revisions))?;
+        self.reload(Some(&id))?;
+        self.message = format!("Saved `{}`", id);
+
+        Ok(())
+    }
+
+    // Create the page like `diary new`
+    fn create(&mut self, screen: &mut Screen, id: &str) -> Result<(), Error> {
+        let (id, memo) = if id.is_empty() {
+            (Local::now().format(DIARY_ID_FORMAT).to_string(), false)
+        } else {
+            (id.to_string(), true)
+        };
+
+        let page = template::initial_page(self.directory, self.config, self.store.as_ref(), &id, memo, None)?;
+
+        let (directory, config) = (self.directory, self.config);
+        self.suspend(screen, |app| create_new_page(app.store.as_mut(), directory, &id, &config.editor, &page))?;
+        self.reload(Some(&id))?;
+        self.message = format!("Created `{}`", id);
+
+        Ok(())
+    }
+
+    fn rename(&mut self, next_id: &str) -> Result<(), Error> {
+        let prev_id = match self.selected_page() {
+            Some(page) => page.id.clone(),
+            None => return Ok(()),
+        };
+
+        self.store.rename(&prev_id, next_id)?;
+        index::rename_page(self.directory, self.store.as_ref(), &prev_id, next_id)?;
+        self.reload(Some(next_id))?;
+        self.message = format!("Renamed `{}` to `{}`", prev_id, next_id);
+
+        Ok(())
+    }
+
+    // Handle a key. Returns false to quit.
+    fn key(&mut self, screen: &mut Screen, key: KeyEvent) -> Result<bool, Error> {
+        if self.mode != Mode::Normal {
+            return self.input_key(screen, key).map(|_| true);
+        }
+
+        let page_height = self.height.max(1);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+            KeyCode::Char('j') | KeyCode::Down => self.select(self.selected + 1),
+            KeyCode::Char('k') | KeyCode::Up => self.select(self.selected.saturating_sub(1)),
+            KeyCode::Char('g') | KeyCode::Home => self.select(0),
+            KeyCode::Char('G') | KeyCode::End => self.select(self.shown.len().saturating_sub(1)),
+            KeyCode::Char('J') | KeyCode::PageDown | KeyCode::Char(' ') => self.preview_top += page_height / 2,
+            KeyCode::Char('K') | KeyCode::PageUp => self.preview_top = self.preview_top.saturating_sub(page_height / 2),
+            KeyCode::Char('e') | KeyCode::Enter => {
+                if let Err(err) = self.edit(screen) {
+                    self.message = format!("{}", err);
+                }
+            },
+            KeyCode::Char('n') => self.mode = Mode::New,
+            KeyCode::Char('r') => {
+                self.mode = Mode::Rename;
+                self.input = self.selected_page().map(|page| page.id.clone()).unwrap_or_default();
+            },
+            KeyCode::Char('/') => self.mode = Mode::Search,
+            KeyCode::Char('d') => self.mode = Mode::Jump,
+            _ => {},
+        }
+
+        Ok(true)
+    }
+
+    // Handle a key in the prompt
+    fn input_key(&mut self, screen: &mut Screen, key: KeyEvent) -> Result<(), Error> {
+        match key.code {
+            KeyCode::Esc => {
+                // Cancelling the search shows all pages again
+                if self.mode == Mode::Search {
+                    self.input.clear();
+                    self.search();
+                }
+                self.mode = Mode::Normal;
+                self.input.clear();
+                self.message = String::from(HELP);
+            },
+            KeyCode::Enter => {
+                let mode = self.mode;
+                let input = self.input.trim().to_string();
+                self.mode = Mode::Normal;
+                self.input.clear();
+
+                let result = match mode {
+                    Mode::Jump => self.jump(&input),
+                    Mode::New => self.create(screen, &input),
+                    Mode::Rename if !input.is_empty() => self.rename(&input),
+                    _ => Ok(()),
+                };
+                if let Err(err) = result {
+                    self.message = format!("{}", err);
+                }
+            },
+            KeyCode::Backspace => {
+                self.input.pop();
+                if self.mode == Mode::Search {
+                    self.search();
+                }
+            },
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.input.push(c);
+                if self.mode == Mode::Search {
+                    self.search();
+                }
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    // Lines of the rendered preview of the selected page
+    fn preview(&self, width: usize) -> Vec<String> {
+        let page = match self.selected_page() {
+            Some(page) => page,
+            None => return vec![String::from("No pages. press n to create one")],
+        };
+
+        let mut contents = String::new();
+        if page.header.insert_title {
+            contents.push_str(&format!("# {}\n\n", page.header.title));
+        }
+        contents.push_str(&page.text);
+
+        let mut lines = vec![format!("{}  {}", page_date(page), page.tags().iter().map(|tag| format!("#{}", tag)).collect::<Vec<_>>().join(" ")), String::new()];
+        lines.extend(markdown::render(&contents, width, true).lines().map(|line| line.to_string()));
+        lines
+    }
+
+    fn draw(&mut self, stdout: &mut Stdout) -> Result<(), Error> {
+        let (columns, rows) = terminal::size().map_err(io_error)?;
+        let (columns, rows) = (columns as usize, rows as usize);
+        let height = rows.saturating_sub(1);
+        self.height = height;
+        let list_width = LIST_WIDTH.min(columns / 3);
+        let preview_width = columns.saturating_sub(list_width + 2).max(1);
+
+        // Keep the selected page visible
+        if self.selected < self.list_top {
+            self.list_top = self.selected;
+        } else if height > 0 && self.selected >= self.list_top + height {
+            self.list_top = self.selected + 1 - height;
+        }
+
+        let preview = self.preview(preview_width);
+        self.preview_top = self.preview_top.min(preview.len().saturating_sub(1));
+
+        for row in 0..height {
+            queue!(stdout, MoveTo(0, row as u16)).map_err(io_error)?;
+
+            let position = self.list_top + row;
+            let entry = match self.shown.get(position) {
+                Some(&i) => {
+                    let page = &self.pages[i];
+                    truncate(&format!("{} {}", page.id, page.header.title), list_width)
+                },
+                None => String::new(),
+            };
+            let padding = " ".repeat(list_width - entry.width());
+            if position == self.selected && !self.shown.is_empty() {
+                queue!(stdout, SetAttribute(Attribute::Reverse), Print(&entry), Print(&padding), SetAttribute(Attribute::Reset)).map_err(io_error)?;
+            } else {
+                queue!(stdout, Print(&entry), Print(&padding)).map_err(io_error)?;
+            }
+
+            let line = preview.get(self.preview_top + row).map(|line| line.as_str()).unwrap_or("");
+            queue!(stdout, Print("│ "), Print(line), SetAttribute(Attribute::Reset), Clear(ClearType::UntilNewLine)).map_err(io_error)?;
+        }
+
+        // Status line or prompt
+        let status = if self.mode == Mode::Normal {
+            truncate(&self.message, columns)
+        } else {
+            truncate(&format!("{}{}", self.mode.prompt(), self.input), columns)
+        };
+        queue!(stdout, MoveTo(0, height as u16), Print(&status), Clear(ClearType::UntilNewLine)).map_err(io_error)?;
+
+        stdout.flush().map_err(io_error)
+    }
+}
+
+// Run the full-screen interface until it is quit
+pub fn run(directory: &str, config: &Config) -> Result<(), Error> {
+    let mut app = App::new(directory, config)?;
+    let mut screen = Screen::new();
+    screen.enter()?;
+
+    loop {
+        app.draw(&mut screen.stdout)?;
+
+        let quit = match event::read().map_err(io_error)? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => !app.key(&mut screen, key)?,
+            Event::Resize(..) => {
+                execute!(screen.stdout, Clear(ClearType::All)).map_err(io_error)?;
+                false
+            },
+            _ => false,
+        };
+        if quit {
+            break;
+        }
+    }
+
+    screen.leave()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use chrono::{Duration, Utc};
+    use store::MemoryStore;
+    use utils::new_page;
+
+    fn store_with_pages() -> Box<dyn Store> {
+        let mut store = MemoryStore::new();
+        let created = Utc::now() - Duration::days(10);
+        for (i, &(id, title, text)) in [
+            ("2018-05-01", "First", "Learning rust"),
+            ("2018-05-02", "Second", "A long page\n\nwith more lines"),
+            ("2018-05-03", "Third", "More rust #work"),
+        ].iter().enumerate() {
+            let mut page = new_page(id);
+            page.header.title = title.to_string();
+            page.header.created = created + Duration::hours(i as i64);
+            page.header.memo = false;
+            page.text = text.to_string();
+            store.insert(page).unwrap();
+        }
+
+        Box::new(store)
+    }
+
+    fn press(app: &mut App, keys: &str) -> bool {
+        let mut screen = Screen::new();
+        keys.chars().all(|c| {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\x08' => KeyCode::Backspace,
+                '\x1b' => KeyCode::Esc,
+                c => KeyCode::Char(c),
+            };
+            app.key(&mut screen, KeyEvent::new(code, KeyModifiers::NONE)).unwrap()
+        })
+    }
+
+    fn shown<'b>(app: &'b App) -> Vec<&'b str> {
+        app.shown.iter().map(|&i| app.pages[i].id.as_str()).collect()
+    }
+
+    fn selected<'b>(app: &'b App) -> &'b str {
+        &app.selected_page().unwrap().id
+    }
+
+    fn with_app<F: FnOnce(&mut App)>(name: &str, store: Box<dyn Store>, f: F) {
+        let dir = env::temp_dir().join(format!("diary-tui-{}-test-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = Config::default();
+        let mut app = App::with_store(dir.to_str().unwrap(), &config, store).unwrap();
+        f(&mut app);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn move_selection() {
+        with_app("move", store_with_pages(), |app| {
+            assert_eq!(shown(app), vec!["2018-05-03", "2018-05-02", "2018-05-01"]);
+            assert_eq!(selected(app), "2018-05-03");
+
+            press(app, "jjj");
+            assert_eq!(selected(app), "2018-05-01");
+            press(app, "k");
+            assert_eq!(selected(app), "2018-05-02");
+            press(app, "gk");
+            assert_eq!(selected(app), "2018-05-03");
+            press(app, "G");
+            assert_eq!(selected(app), "2018-05-01");
+
+            // Scroll by half of the height, and start at the top of another page
+            app.height = 10;
+            press(app, "JJ ");
+            assert_eq!(app.preview_top, 15);
+            press(app, "K");
+            assert_eq!(app.preview_top, 10);
+            press(app, "k");
+            assert_eq!(app.preview_top, 0);
+
+            assert!(!press(app, "q"));
+            assert!(!app.key(&mut Screen::new(), KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)).unwrap());
+        });
+    }
+
+    #[test]
+    fn search_and_jump() {
+        with_app("search", store_with_pages(), |app| {
+            press(app, "/rust");
+            assert_eq!(app.mode, Mode::Search);
+            assert_eq!(shown(app), vec!["2018-05-03", "2018-05-01"]);
+            press(app, " -work");
+            assert_eq!(shown(app), vec!["2018-05-01"]);
+            assert_eq!(app.message, "1 pages match");
+            press(app, "\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08");
+            assert_eq!(shown(app), vec!["2018-05-03", "2018-05-02", "2018-05-01"]);
+
+            // Incomplete queries keep the results
+            press(app, "first");
+            assert_eq!(shown(app), vec!["2018-05-01"]);
+            app.input = "first OR".to_string();
+            app.search();
+            assert_eq!(shown(app), vec!["2018-05-01"]);
+            app.input = "-".to_string();
+            app.search();
+            assert_eq!(shown(app), vec!["2018-05-01"]);
+            press(app, "\x08");
+            assert_eq!(shown(app).len(), 3);
+
+            // Enter keeps the results and Esc cancels the search
+            press(app, "second\n");
+            assert_eq!((app.mode, shown(app)), (Mode::Normal, vec!["2018-05-02"]));
+            press(app, "/x\x1b");
+            assert_eq!((app.mode, app.message.as_str()), (Mode::Normal, HELP));
+            assert_eq!(shown(app).len(), 3);
+
+            press(app, "d2018-05-02\n");
+            assert_eq!(selected(app), "2018-05-02");
+            press(app, "d2018-06\n");
+            assert_eq!(selected(app), "2018-05-03");
+            press(app, "d2018-04-30\n");
+            assert_eq!(app.message, "No pages on or before 2018-04-30");
+            assert_eq!(selected(app), "2018-05-03");
+        });
+    }
+
+    #[test]
+    fn rename_selected() {
+        with_app("rename", store_with_pages(), |app| {
+            press(app, "jr");
+            assert_eq!((app.mode, app.input.as_str()), (Mode::Rename, "2018-05-02"));
+
+            press(app, "\x08\x08x\n");
+            assert_eq!(app.message, "Renamed `2018-05-02` to `2018-05-x`");
+            assert_eq!(shown(app), vec!["2018-05-03", "2018-05-x", "2018-05-01"]);
+            assert_eq!(selected(app), "2018-05-x");
+            assert!(app.store.exists("2018-05-x").unwrap() && !app.store.exists("2018-05-02").unwrap());
+
+            // The error is shown
+            press(app, "r\x08\x08\x08\x08\x08\x08\x08\x08\x08../x\n");
+            assert!(app.message.starts_with("Invalid ID `../x`"), "{}", app.message);
+            assert_eq!(app.mode, Mode::Normal);
+        });
+    }
+
+    #[test]
+    fn preview_pages() {
+        with_app("preview", Box::new(MemoryStore::new()), |app| {
+            assert_eq!(app.preview(40), vec!["No pages. press n to create one"]);
+            press(app, "jkGr\n");
+            assert_eq!(app.message, HELP);
+        });
+
+        with_app("preview-pages", store_with_pages(), |app| {
+            let preview = app.preview(40);
+            assert!(preview[0].ends_with("  #work"), "{}", preview[0]);
+            assert!(preview[2].contains("Third"));
+            assert!(preview.iter().any(|line| line.contains("More rust")));
+        });
+
+        assert_eq!(truncate("日記 diary", 5), "日記 ");
+        assert_eq!(truncate("日記 diary", 3), "日");
+        assert_eq!(truncate("diary", 10), "diary");
+    }
+}
@@ -1,12 +1,23 @@
+use std::env;
 use std::io;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::process;
 use std::process::Command;
 
 use toml;
-use chrono::{Utc};
+use chrono::{Utc, Local};
 use page::{Page, PageHeader, PageError};
+use config::Retention;
+use crypto;
+use error::Error;
+use index;
+use journal::TEMPORARY_EXTENSION;
+use revision;
+use store::Store;
 
 // Directory name to save pages
 pub const PAGES_DIR: &str = "pages";
@@ -16,6 +27,14 @@ pub const PAGE_EXTENSION: &str = "page";
 pub const HEAD_FILENAME: &str = "HEAD";
 // Temporary file to edit page
 pub const TEMPORARY_FILE_TO_EDIT: &str = "EDIT_PAGE";
+// Directory to move removed pages into
+pub const TRASH_DIR: &str = "trash";
+// Directory to save previous versions of pages, `revisions/<id>/<number>.page`
+pub const REVISIONS_DIR: &str = "revisions";
+// Directory to save edits which could not be saved to pages
+pub const RECOVERY_DIR: &str = "recovery";
+// Format of time in names of recovery files
+pub const RECOVERY_TIME_FORMAT: &str = "%Y%m%d%H%M%S";
 // Invalid characters in file path
 pub const INVALID_CHARACTERS: [&str; 11] = ["\\", "/", ":", ",", ";", "*", "?", "\"", "<", ">", "|"];
 
@@ -24,6 +43,8 @@ pub struct TemporaryPageHeader {
     pub title: String,
     pub insert_title: bool,
     pub memo: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 impl TemporaryPageHeader {
@@ -32,6 +53,7 @@ impl TemporaryPageHeader {
             title: header.title.clone(),
             insert_title: header.insert_title,
             memo: header.memo,
+            tags: header.tags.clone(),
         }
     }
 }
@@ -43,6 +65,7 @@ pub struct TemporaryPage {
 }
 
 impl TemporaryPage {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<TemporaryPage, PageError> {
         let tmp: Vec<&str> = s.splitn(3, "---").collect();
         if tmp.len() < 3 {
@@ -76,25 +99,28 @@ impl TemporaryPage {
 
     pub fn apply(&self, page: &mut Page) {
         page.header.title = self.header.title.clone();
-        page.header.insert_title = self.header.insert_title.clone();
-        page.header.memo = self.header.memo.clone();
+        page.header.insert_title = self.header.insert_title;
+        page.header.memo = self.header.memo;
+        page.header.tags = self.header.tags.clone();
         page.text = self.text.clone();
     }
 }
 
 // Check if id is valid
-pub fn is_valid_id(id: &str) -> Result<(), String> {
-    if id == "" {
-        return Err(String::from("empty id is unavaialble"));
+pub fn is_valid_id(id: &str) -> Result<(), Error> {
+    let invalid = |reason: String| Err(Error::InvalidId { id: id.to_string(), reason });
+
+    if id.is_empty() {
+        return invalid(String::from("empty id is unavaialble"));
     }
 
     if id == "NULL" {
-        return Err(String::from("`NULL` is unavailable"));
+        return invalid(String::from("`NULL` is unavailable"));
     }
 
     // if id contains invalid characters
     if INVALID_CHARACTERS.iter().any(|c| id.contains(c)) {
-        return Err(format!("invalid character ({})", INVALID_CHARACTERS.join(" ")))
+        return invalid(format!("invalid character ({})", INVALID_CHARACTERS.join(" ")));
     }
 
     Ok(())
@@ -109,150 +135,327 @@ pub fn read_file(path: &PathBuf) -> Result<String, io::Error> {
     Ok(contents)
 }
 
+// Write to a temporary file and rename it to the path, so that the file is never half-written
 pub fn write_file(path: &PathBuf, contents: &str) -> Result<(), io::Error> {
-    let mut file = fs::File::create(path)?;
-
-    file.write_all(contents.as_bytes())?;
+    write_file_with_mode(path, contents, 0o666)
+}
 
-    Ok(())
+// Same as `write_file`, but the file can be read only by the user
+pub fn write_private_file(path: &PathBuf, contents: &str) -> Result<(), io::Error> {
+    write_file_with_mode(path, contents, 0o600)
 }
 
-pub fn get_head_id(directory: &str) -> Result<String, String> {
-    // Get filepath to save head page id
-    let head_filepath = Path::new(directory).join(HEAD_FILENAME);
-    let head_filepath = head_filepath.as_path();
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn write_file_with_mode(path: &PathBuf, contents: &str, mode: u32) -> Result<(), io::Error> {
+    let mut temp_path = path.clone().into_os_string();
+    temp_path.push(format!(".{}", TEMPORARY_EXTENSION));
+    let temp_path = PathBuf::from(temp_path);
 
-    // Get head page id
-    let mut file = match fs::File::open(head_filepath) {
-        Ok(file) => file,
-        Err(err) => return Err(format!("Unable to open HEAD file `{}`: {}", head_filepath.to_string_lossy(), err)),
-    };
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(mode);
+
+    let mut file = options.open(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+
+    fs::rename(&temp_path, path)?;
 
-    let mut head_id = String::new();
-    if let Err(err) = file.read_to_string(&mut head_id) {
-        return Err(format!("Unable to read HEAD file `{}`: {}", head_filepath.to_string_lossy(), err));
+    // Flush the rename
+    #[cfg(unix)]
+    {
+        if let Some(parent) = path.parent() {
+            fs::File::open(parent)?.sync_all()?;
+        }
     }
 
-    Ok(head_id)
+    Ok(())
 }
 
-pub fn get_page_by_id(directory: &str, id: &str) -> Result<Page, String> {
-    // Get page filepath
-    let filepath = Path::new(directory).join(PAGES_DIR).join(format!("{}.{}", id, PAGE_EXTENSION));
-    let filepath = filepath.as_path();
+pub fn create_new_page(store: &mut dyn Store, directory: &str, id: &str, editor: &str, initial_page: &TemporaryPage) -> Result<(), Error> {
+    is_valid_id(id)?;
 
-    // Read page file
-    let mut file = match fs::File::open(filepath) {
-        Ok(file) => file,
-        Err(err) => return Err(format!("Unable to open page file `{}`: {}", filepath.to_string_lossy(), err)),
-    };
-
-    let mut contents = String::new();
-    if let Err(err) = file.read_to_string(&mut contents) {
-        return Err(format!("Unable to read page file `{}`: {}", filepath.to_string_lossy(), err));
+    // If page already exists
+    if store.exists(id)? {
+        return Err(Error::AlreadyExists(format!("`{}` already exists. use `diary edit {}`", id, id)));
     }
 
-    // Parse page file contents
-    let page = match Page::from_str(&contents, id) {
-        Ok(page) => page,
-        Err(err) => return Err(format!("{}", err)),
-    };
+    let mut page = new_page(id);
+    initial_page.apply(&mut page);
 
-    Ok(page)
+    // Edit page
+    let page = edit_page(directory, page, editor)?;
+
+    store.insert(page.clone())?;
+    index::update_page(directory, &page)?;
+    remove_edit_file(directory, id)?;
+
+    Ok(())
 }
 
-pub fn create_new_page(directory: &str, id: &str, editor: &str, initial_page: &TemporaryPage) -> Result<(), String> {
-    if let Err(err) = is_valid_id(&id) {
-        return Err(format!("Invalid ID: {}", err));
-    }
+// Create the page with the initial contents without opening the editor
+pub fn create_page(store: &mut dyn Store, directory: &str, id: &str, initial_page: &TemporaryPage) -> Result<(), Error> {
+    is_valid_id(id)?;
 
-    // If page file already exists
-    let new_file_path = Path::new(directory).join(PAGES_DIR).join(format!("{}.{}", id, PAGE_EXTENSION));
-    if new_file_path.exists() {
-        return Err(format!("`{}` already exists. use `diary edit {}`", id, id));
+    if store.exists(id)? {
+        return Err(Error::AlreadyExists(format!("`{}` already exists. use `diary append {}`", id, id)));
     }
 
-    // Get head id
-    let head_id = get_head_id(directory)?;
-    
-    let mut page = Page {
-        id: id.clone().to_string(),
+    let mut page = new_page(id);
+    initial_page.apply(&mut page);
+    page.header.updated.push(Utc::now());
+
+    store.insert(page.clone())?;
+    index::update_page(directory, &page)?;
+
+    Ok(())
+}
+
+// Page not linked to any page yet
+pub fn new_page(id: &str) -> Page {
+    Page {
+        id: id.to_string(),
         header: PageHeader {
             title: id.to_string(),
             insert_title: true,
             created: Utc::now(),
             updated: Vec::new(),
             memo: true,
-            prev: head_id.clone(),
+            tags: Vec::new(),
+            prev: "NULL".to_string(),
             next: "NULL".to_string(),
         },
         text: String::new(),
-    };
-    initial_page.apply(&mut page);
-
-    // Edit page
-    let page = edit_page(directory, page, editor)?;
+    }
+}
 
-    // Write page
-    write_page(directory, &id, &page)?;
+// Create a directory which can be read only by the user
+fn create_private_dir(path: &Path) -> Result<(), Error> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
 
-    // Update next of head page
-    if head_id != "NULL" {
-        let mut head_page = get_page_by_id(directory, &head_id)?;
-        head_page.header.next = id.clone().to_string();
+    builder.create(path)
+        .map_err(|err| Error::io(format!("Unable to create directory `{}`", path.to_string_lossy()), err))
+}
 
-        write_page(directory, &head_id, &head_page)?;
+// Directory of temporary files to edit and recovery files. Decrypted edits of encrypted diaries
+// are kept in the private runtime directory, which is usually tmpfs, when it is available.
+pub fn edit_dir(directory: &str) -> Result<PathBuf, Error> {
+    if crypto::is_enabled(directory) {
+        if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR").filter(|dir| Path::new(dir).is_dir()) {
+            let dir = Path::new(&runtime_dir).join("diary");
+            create_private_dir(&dir)?;
+            return Ok(dir);
+        }
     }
 
-    // Update head file
-    let head_path = Path::new(directory).join(HEAD_FILENAME);   
-    let mut head_file = fs::File::create(&head_path)
-        .map_err(|err| format!("Unable to open head file `{}`: {}", head_path.to_string_lossy(), err))?;
+    Ok(PathBuf::from(directory))
+}
 
-    head_file.write_all(id.as_bytes())
-        .map_err(|err| format!("Unable to write head to file `{}`: {}", head_path.to_string_lossy(), err))?;
+// Temporary file to edit the page in this process
+pub fn edit_file_path(directory: &str, id: &str) -> Result<PathBuf, Error> {
+    Ok(edit_dir(directory)?.join(format!("{}.{}.{}", TEMPORARY_FILE_TO_EDIT, process::id(), id)))
+}
 
-    Ok(())
+pub fn remove_edit_file(directory: &str, id: &str) -> Result<(), Error> {
+    let path = edit_file_path(directory, id)?;
+    fs::remove_file(&path)
+        .map_err(|err| Error::io(format!("Unable to remove temporary file `{}`", path.to_string_lossy()), err))
 }
 
-pub fn edit_page(directory: &str, page: Page, editor: &str) -> Result<Page, String> {
-    let mut page = page;
+// Find temporary files left by editors of other processes, returning pairs of the path and the page id.
+// The id is unknown for `EDIT_PAGE` left in the app directory by older versions.
+pub fn find_stale_edit_files(directory: &str) -> Result<Vec<(PathBuf, Option<String>)>, Error> {
+    let edit_dir = edit_dir(directory)?;
+    let entries = fs::read_dir(&edit_dir)
+        .map_err(|err| Error::io(format!("Unable to list files in directory `{}`", edit_dir.to_string_lossy()), err))?;
+
+    let prefix = format!("{}.", TEMPORARY_FILE_TO_EDIT);
+    let mut files = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(&prefix) {
+            continue;
+        }
 
-    let temp_page = TemporaryPage::from_page(&page);
-    let file_to_edit_path = Path::new(directory).join(TEMPORARY_FILE_TO_EDIT);
+        // EDIT_PAGE.<pid>.<id>
+        let mut parts = name[prefix.len()..].splitn(2, '.');
+        let pid = parts.next().and_then(|pid| pid.parse::<u32>().ok());
+        if let (Some(pid), Some(id)) = (pid, parts.next()) {
+            if pid != process::id() {
+                files.push((entry.path(), Some(id.to_string())));
+            }
+        }
+    }
 
-    // Write to temporary page file
-    let temp_page_str = temp_page.to_str().unwrap();
-    write_file(&file_to_edit_path, &temp_page_str)
-        .map_err(|err| format!("Unable to write to temporary page file `{}`: {}", file_to_edit_path.to_string_lossy(), err))?;
+    let legacy_path = Path::new(directory).join(TEMPORARY_FILE_TO_EDIT);
+    if legacy_path.is_file() {
+        files.push((legacy_path, None));
+    }
 
-    // Execute editor
+    files.sort();
+    Ok(files)
+}
+
+pub fn run_editor(editor: &str, path: &Path) -> Result<(), Error> {
     let mut command =
         if cfg!(target_os = "windows") {
             Command::new("cmd")
-                .args(&["/c", &format!("{} {}", editor, file_to_edit_path.to_string_lossy())])
+                .args(["/c", &format!("{} {}", editor, path.to_string_lossy())])
                 .spawn()
-                .map_err(|err| format!("Unable to execute editor `cmd /c {}`: {}", editor, err))?
+                .map_err(|err| Error::Editor(format!("Unable to execute editor `cmd /c {}`: {}", editor, err)))?
         } else {
             Command::new("sh")
-                .args(&["-c", &format!("{} {}", editor, file_to_edit_path.to_string_lossy())])
+                .args(["-c", &format!("{} {}", editor, path.to_string_lossy())])
                 .spawn()
-                .map_err(|err| format!("Unable to execute editor `sh -c {}`: {}", editor, err))?
+                .map_err(|err| Error::Editor(format!("Unable to execute editor `sh -c {}`: {}", editor, err)))?
         };
 
     let status = command.wait()
-        .map_err(|err| format!("Unable to wait editor `{}`: {}", editor, err))?;
+        .map_err(|err| Error::Editor(format!("Unable to wait editor `{}`: {}", editor, err)))?;
 
     // Error if exit code is not 0
     if !status.success() {
-        return Err(format!("Failed editor `{}`", editor));
+        return Err(Error::Editor(format!("Failed editor `{}`", editor)));
     }
 
-    // Read and parse temporary file
-    let contents = read_file(&file_to_edit_path)
-        .map_err(|err| format!("Unable to read temporary file `{}`: {}", file_to_edit_path.to_string_lossy(), err))?;
+    Ok(())
+}
 
-    let temp_page = TemporaryPage::from_str(&contents).map_err(|err| format!("{}", err))?;
+// Pipe the contents to the pager
+pub fn run_pager(pager: &str, contents: &str) -> Result<(), Error> {
+    let mut command =
+        if cfg!(target_os = "windows") {
+            Command::new("cmd")
+                .args(["/c", pager])
+                .stdin(process::Stdio::piped())
+                .spawn()
+                .map_err(|err| Error::io(format!("Unable to execute pager `cmd /c {}`", pager), err))?
+        } else {
+            Command::new("sh")
+                .args(["-c", pager])
+                .stdin(process::Stdio::piped())
+                .spawn()
+                .map_err(|err| Error::io(format!("Unable to execute pager `sh -c {}`", pager), err))?
+        };
+
+    if let Some(mut stdin) = command.stdin.take() {
+        // The pager may quit before reading all
+        match stdin.write_all(contents.as_bytes()) {
+            Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => {},
+            result => result.map_err(|err| Error::io(format!("Unable to write to pager `{}`", pager), err))?,
+        }
+    }
+
+    command.wait()
+        .map_err(|err| Error::io(format!("Unable to wait pager `{}`", pager), err))?;
+
+    Ok(())
+}
+
+// Move a temporary file into the recovery directory to keep the edit
+pub fn save_recovery(directory: &str, path: &Path, id: &str) -> Result<PathBuf, Error> {
+    let recovery_dir = edit_dir(directory)?.join(RECOVERY_DIR);
+    if path.parent() == Some(recovery_dir.as_path()) {
+        return Ok(path.to_path_buf());
+    }
+
+    if !recovery_dir.exists() {
+        create_private_dir(&recovery_dir)?;
+    }
+
+    // <time>.<id>
+    let now = Local::now().format(RECOVERY_TIME_FORMAT);
+    let recovery_path = recovery_dir.join(format!("{}.{}", now, id));
+    fs::rename(path, &recovery_path)
+        .map_err(|err| Error::io(format!("Unable to move `{}` to `{}`", path.to_string_lossy(), recovery_path.to_string_lossy()), err))?;
+
+    Ok(recovery_path)
+}
+
+// Get recovery files sorted by saved time, returning tuples of the path, the saved time and the page id
+pub fn get_recoveries(directory: &str) -> Result<Vec<(PathBuf, String, String)>, Error> {
+    let recovery_dir = edit_dir(directory)?.join(RECOVERY_DIR);
+    if !recovery_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let entries = fs::read_dir(&recovery_dir)
+        .map_err(|err| Error::io(format!("Unable to list files in directory `{}`", recovery_dir.to_string_lossy()), err))?;
+
+    let mut recoveries = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let mut parts = name.splitn(2, '.');
+        if let (Some(time), Some(id)) = (parts.next(), parts.next()) {
+            recoveries.push((entry.path(), time.to_string(), id.to_string()));
+        }
+    }
+
+    recoveries.sort();
+    Ok(recoveries)
+}
+
+// Show an error as comments above the header of a temporary file
+fn prepend_error(contents: &str, error: &str) -> String {
+    // Remove the comments added before
+    let contents = match contents.find("---") {
+        Some(pos) => &contents[pos..],
+        None => contents,
+    };
+
+    let comments: String = error.lines().map(|line| format!("# {}\n", line)).collect();
+    format!("# Unable to save the page. Fix the error below and save again.\n{}{}", comments, contents)
+}
+
+// Edit a temporary file until it can be parsed. If the editor fails or the file is saved
+// without any change after an error, the file is moved to the recovery directory.
+fn edit_temporary_file(directory: &str, path: &Path, id: &str, editor: &str) -> Result<TemporaryPage, Error> {
+    let path_buf = path.to_path_buf();
+    loop {
+        let before = read_file(&path_buf)
+            .map_err(|err| Error::io(format!("Unable to read temporary file `{}`", path.to_string_lossy()), err))?;
+
+        if let Err(err) = run_editor(editor, path) {
+            let recovery_path = save_recovery(directory, path, id)?;
+            return Err(Error::Editor(format!("{}. The edit is saved to `{}`. use `diary recover`", err, recovery_path.to_string_lossy())));
+        }
+
+        // Read and parse temporary file
+        let contents = read_file(&path_buf)
+            .map_err(|err| Error::io(format!("Unable to read temporary file `{}`", path.to_string_lossy()), err))?;
+
+        let err = match TemporaryPage::from_str(&contents) {
+            Ok(temp_page) => return Ok(temp_page),
+            Err(err) => err,
+        };
+
+        if contents == before {
+            let recovery_path = save_recovery(directory, path, id)?;
+            return Err(Error::Parse(format!("{}. The edit is saved to `{}`. use `diary recover`", err, recovery_path.to_string_lossy())));
+        }
+
+        write_private_file(&path_buf, &prepend_error(&contents, &format!("{}", err)))
+            .map_err(|err| Error::io(format!("Unable to write to temporary page file `{}`", path.to_string_lossy()), err))?;
+    }
+}
+
+pub fn edit_page(directory: &str, page: Page, editor: &str) -> Result<Page, Error> {
+    let mut page = page;
+
+    let temp_page = TemporaryPage::from_page(&page);
+    let file_to_edit_path = edit_file_path(directory, &page.id)?;
+
+    // Write to temporary page file
+    let temp_page_str = temp_page.to_str().unwrap();
+    write_private_file(&file_to_edit_path, &temp_page_str)
+        .map_err(|err| Error::io(format!("Unable to write to temporary page file `{}`", file_to_edit_path.to_string_lossy()), err))?;
+
+    // Execute editor
+    let temp_page = edit_temporary_file(directory, &file_to_edit_path, &page.id, editor)?;
     temp_page.apply(&mut page);
 
     // Update updated times of header
@@ -261,67 +464,217 @@ pub fn edit_page(directory: &str, page: Page, editor: &str) -> Result<Page, Stri
     Ok(page)
 }
 
-pub fn edit_page_by_id(directory: &str, id: &str, editor: &str) -> Result<(), String> {
-    let new_file_path = Path::new(directory).join(PAGES_DIR).join(format!("{}.{}", id, PAGE_EXTENSION));
-    if !new_file_path.exists() {
-        return Err(format!("`{}` does not exists. use `diary new {}`", id, id));
+pub fn edit_page_by_id(store: &mut dyn Store, directory: &str, id: &str, editor: &str, retention: &Retention) -> Result<(), Error> {
+    if !store.exists(id)? {
+        return Err(Error::NotFound(format!("`{}` does not exists. use `diary new {}`", id, id)));
     }
 
     // Get page to edit
-    let page = get_page_by_id(directory, id)?;
+    let previous = store.get(id)?;
 
     // Edit page
-    let page = edit_page(directory, page, editor)?;
+    let page = edit_page(directory, previous.clone(), editor)?;
 
-    // Write page
-    write_page(directory, &id, &page)?;
+    // Write page keeping the previous version
+    let changes = revision::save_changes(store, &previous, &page, retention)?;
+    store.apply(changes)?;
+    index::update_page(directory, &page)?;
+    remove_edit_file(directory, id)?;
 
     Ok(())
 }
 
-pub fn write_page(directory: &str, id: &str, page: &Page) -> Result<(), String> {
-    let path = Path::new(directory).join(PAGES_DIR).join(format!("{}.{}", id, PAGE_EXTENSION));
-    let mut page_file = fs::File::create(&path)
-        .map_err(|err| format!("Unable to open page file `{}`: {}", path.to_string_lossy(), err))?;
+// Add the text to the end of the page without opening the editor. Blank text leaves the page as is.
+pub fn append_to_page(store: &mut dyn Store, directory: &str, id: &str, text: &str, retention: &Retention) -> Result<(), Error> {
+    if !store.exists(id)? {
+        return Err(Error::NotFound(format!("`{}` does not exists. use `diary new {}`", id, id)));
+    }
+    if text.trim().is_empty() {
+        return Ok(());
+    }
 
-    let page_str = page.to_str().map_err(|err| format!("Unable to serialize page `{}`: {}", id, err))?;
+    let previous = store.get(id)?;
+    let mut page = previous.clone();
+    page.text = append_text(&page.text, text);
+    page.header.updated.push(Utc::now());
 
-    page_file.write_all(page_str.as_bytes())
-        .map_err(|err| format!("Unable to write page to file `{}`: {}", path.to_string_lossy(), err))?;
+    let changes = revision::save_changes(store, &previous, &page, retention)?;
+    store.apply(changes)?;
+    index::update_page(directory, &page)?;
 
     Ok(())
 }
 
-pub fn edit_id(directory: &str, prev_id: &str, next_id: &str) -> Result<(), String> {
-    if let Err(err) = is_valid_id(next_id) {
-        return Err(format!("Invalid ID `{}`: {}", next_id, err));
+// The text followed by the appended lines, which always end with a newline
+pub fn append_text(text: &str, appended: &str) -> String {
+    let mut result = text.to_string();
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
     }
+    result.push_str(appended.trim_end_matches('\n'));
+    result.push('\n');
+    result
+}
+
+// Reopen a temporary file left by a crashed editor or a recovery file and save it to the page
+pub fn recover_edit_file(store: &mut dyn Store, directory: &str, path: &Path, id: &str, editor: &str, retention: &Retention) -> Result<(), Error> {
+    let temp_page = edit_temporary_file(directory, path, id, editor)?;
 
-    let next_path = Path::new(directory).join(PAGES_DIR).join(format!("{}.{}", next_id, PAGE_EXTENSION));
-    if next_path.exists() {
-        return Err(format!("`{}` exists.", next_id));
+    if store.exists(id)? {
+        let previous = store.get(id)?;
+        let mut page = previous.clone();
+        temp_page.apply(&mut page);
+        page.header.updated.push(Utc::now());
+
+        let changes = revision::save_changes(store, &previous, &page, retention)?;
+        store.apply(changes)?;
+        index::update_page(directory, &page)?;
+    } else {
+        // The page was being created
+        let mut page = new_page(id);
+        temp_page.apply(&mut page);
+        page.header.updated.push(Utc::now());
+
+        store.insert(page.clone())?;
+        index::update_page(directory, &page)?;
     }
 
-    let page = get_page_by_id(directory, prev_id)?;
-    let page_path = Path::new(directory).join(PAGES_DIR).join(format!("{}.{}", prev_id, PAGE_EXTENSION));
-    fs::rename(page_path, next_path)
-        .map_err(|err| format!("Unable to rename file from `{}` to `{}`: {}", prev_id, next_id, err))?;
+    fs::remove_file(path)
+        .map_err(|err| Error::io(format!("Unable to remove temporary file `{}`", path.to_string_lossy()), err))?;
+
+    Ok(())
+}
 
-    if page.header.prev != "NULL" {
-        let mut prev_page = get_page_by_id(directory, &page.header.prev)?;
-        prev_page.header.next = String::from(next_id);
-        write_page(directory, &prev_page.id, &prev_page)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use store::MemoryStore;
+
+    #[test]
+    fn append_lines() {
+        assert_eq!(append_text("", "first"), "first\n");
+        assert_eq!(append_text("first", "second\n\n"), "first\nsecond\n");
+        assert_eq!(append_text("first\n", "second\nthird"), "first\nsecond\nthird\n");
     }
 
-    if page.header.next != "NULL" {
-        let mut next_page = get_page_by_id(directory, &page.header.next)?;
-        next_page.header.prev = String::from(next_id);
-        write_page(directory, &next_page.id, &next_page)?;
-    } else {
-        // Update HEAD
-        write_file(&Path::new(directory).join(HEAD_FILENAME), next_id)
-            .map_err(|err| format!("Unable to update HEAD: {}", err))?;
+    #[test]
+    fn create_and_append() {
+        let dir = env::temp_dir().join(format!("diary-append-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let directory = dir.to_str().unwrap();
+        let retention = Retention::default();
+        let mut store = MemoryStore::new();
+
+        let mut initial_page = TemporaryPage::from_page(&new_page("2018-05-01"));
+        initial_page.header.title = "First".to_string();
+        initial_page.text = "first\n".to_string();
+        create_page(&mut store, directory, "2018-05-01", &initial_page).unwrap();
+
+        let page = store.get("2018-05-01").unwrap();
+        assert_eq!((page.header.title.as_str(), page.text.as_str()), ("First", "first\n"));
+        assert_eq!(page.header.updated.len(), 1);
+        assert_eq!(store.head().unwrap(), "2018-05-01");
+
+        match create_page(&mut store, directory, "2018-05-01", &initial_page) {
+            Err(Error::AlreadyExists(_)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(create_page(&mut store, directory, "../2018-05-02", &initial_page).is_err());
+
+        // Blank input changes nothing
+        for text in &["", "\n", "  \n"] {
+            append_to_page(&mut store, directory, "2018-05-01", text, &retention).unwrap();
+        }
+        assert_eq!(store.get("2018-05-01").unwrap().header.updated.len(), 1);
+        assert!(store.revisions("2018-05-01").unwrap().is_empty());
+
+        append_to_page(&mut store, directory, "2018-05-01", "second", &retention).unwrap();
+        let page = store.get("2018-05-01").unwrap();
+        assert_eq!(page.text, "first\nsecond\n");
+        assert_eq!(page.header.updated.len(), 2);
+        assert_eq!(store.revisions("2018-05-01").unwrap().len(), 1);
+
+        match append_to_page(&mut store, directory, "2018-05-02", "text", &retention) {
+            Err(Error::NotFound(_)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    Ok(())
+    // Editor running the script with the number of the call, which starts at 0, as `$n`
+    fn script_editor(dir: &Path, script: &str) -> String {
+        let path = dir.join("editor.sh");
+        let count = dir.join("count");
+        write_file(&path, &format!("n=$(cat '{}' 2>/dev/null || echo 0)\necho $((n + 1)) > '{}'\n{}\n",
+                                   count.to_string_lossy(), count.to_string_lossy(), script)).unwrap();
+        format!("sh '{}'", path.to_string_lossy())
+    }
+
+    #[test]
+    fn reopen_editor_with_error() {
+        let dir = env::temp_dir().join(format!("diary-editor-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let directory = dir.to_str().unwrap();
+
+        // Break the header, then fix it after checking the error shown
+        let editor = script_editor(&dir, &format!("if [ $n = 0 ]; then printf -- '---\\ntitle = \\n---\\nbroken' > \"$1\"; \
+                                                   else cp \"$1\" '{}'; printf -- '---\\ntitle = \"Fixed\"\\ninsert_title = true\\nmemo = false\\n---\\nfixed' > \"$1\"; fi",
+                                                  dir.join("reopened").to_string_lossy()));
+        let page = edit_page(directory, new_page("2018-05-01"), &editor).unwrap();
+        assert_eq!((page.header.title.as_str(), page.text.as_str(), page.header.memo), ("Fixed", "fixed", false));
+        assert_eq!(read_file(&dir.join("count")).unwrap(), "2\n");
+
+        let reopened = read_file(&dir.join("reopened")).unwrap();
+        assert!(reopened.starts_with("# Unable to save the page. Fix the error below and save again.\n# "));
+        assert!(reopened.ends_with("---\ntitle = \n---\nbroken"));
+
+        // Comments added before are replaced
+        let contents = prepend_error(&reopened, "another\nerror");
+        assert_eq!(contents, "# Unable to save the page. Fix the error below and save again.\n# another\n# error\n---\ntitle = \n---\nbroken");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_recovery_after_failure() {
+        let dir = env::temp_dir().join(format!("diary-recovery-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let directory = dir.to_str().unwrap();
+
+        // Saved without any change after the error
+        let editor = script_editor(&dir, "if [ $n = 0 ]; then printf -- '---\\nbroken' > \"$1\"; fi");
+        match edit_page(directory, new_page("2018-05-01"), &editor) {
+            Err(Error::Parse(_)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(read_file(&dir.join("count")).unwrap(), "2\n");
+
+        // Failed editor
+        match edit_page(directory, new_page("2018-05-02"), "false") {
+            Err(Error::Editor(_)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let recoveries = get_recoveries(directory).unwrap();
+        let ids: Vec<&str> = recoveries.iter().map(|(_, _, id)| id.as_str()).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&"2018-05-01") && ids.contains(&"2018-05-02"));
+        for (path, _, id) in &recoveries {
+            let contents = read_file(path).unwrap();
+            if id == "2018-05-01" {
+                assert!(contents.starts_with("# Unable to save the page.") && contents.ends_with("---\nbroken"));
+            } else {
+                assert!(TemporaryPage::from_str(&contents).is_ok());
+            }
+        }
+
+        // No temporary file is left
+        assert!(find_stale_edit_files(directory).unwrap().is_empty());
+        assert!(!edit_file_path(directory, "2018-05-01").unwrap().exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }